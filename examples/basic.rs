@@ -104,11 +104,11 @@ fn setup(
             name: "Room 1".to_string(),
             operation: BooleanOp::Subtract,
             // Cut the brushlet with a knife
-            knives: vec![Knife {
-                normal: DVec3::new(-1.0, -1.0, -1.0),
-                distance_from_origin: 4.0,
-                material_index: MyMaterials::ProtoGreen.into(),
-            }],
+            knives: vec![Knife::new(
+                DVec3::new(-1.0, -1.0, -1.0),
+                4.0,
+                MyMaterials::ProtoGreen.into(),
+            )],
             inverted: true,
         },
     ));
@@ -141,13 +141,16 @@ fn setup(
     brush.transform(DAffine3::from_translation(DVec3::new(-4.0, 0.0, -4.0)));
 
     // Cut at the brush level with a knife to cut both rooms at once
-    brush.settings.knives = vec![Knife {
-        normal: DVec3::new(1.0, 1.0, 0.0),
-        distance_from_origin: 4.0,
-        material_index: MyMaterials::ProtoGrey.into(),
-    }];
+    brush.settings.knives = vec![Knife::new(
+        DVec3::new(1.0, 1.0, 0.0),
+        4.0,
+        MyMaterials::ProtoGrey.into(),
+    )];
 
-    let mut meshes_with_materials = brush.to_mesh_data().to_bevy_meshes();
+    let mut meshes_with_materials = brush
+        .to_mesh_data()
+        .to_batched_mesh(DEFAULT_WELD_TOLERANCE)
+        .to_bevy_meshes();
 
     let mut pillar_brush = Brush::new("Pillar");
     pillar_brush
@@ -155,7 +158,12 @@ fn setup(
         .push(create_beveled_pillar(DVec3::new(2.0, 0.0, 2.0)));
 
     // Spawn each mesh with the appropriate material
-    meshes_with_materials.extend(pillar_brush.to_mesh_data().to_bevy_meshes());
+    meshes_with_materials.extend(
+        pillar_brush
+            .to_mesh_data()
+            .to_batched_mesh(DEFAULT_WELD_TOLERANCE)
+            .to_bevy_meshes(),
+    );
     for (mesh, material_index) in meshes_with_materials {
         let material = match material_index {
             0 => material_proto_grey.clone(),
@@ -175,19 +183,12 @@ fn setup(
 }
 
 fn create_beveled_pillar(origin: DVec3) -> Brushlet {
-    let pillar_width = 1.0;
-    let pillar_height = 4.0;
-    let pillar_depth = 1.0;
-    let bevel_size = 0.1;
-    let sqrt2 = 2.0_f64.sqrt();
-    let base_distance = (pillar_width / 2.0 + pillar_depth / 2.0 - bevel_size) / sqrt2;
-
-    Brushlet::from_cuboid(
+    let stem = Brushlet::from_cuboid(
         brusher::primitives::Cuboid {
             origin,
-            width: pillar_width,
-            height: pillar_height,
-            depth: pillar_depth,
+            width: 1.0,
+            height: 4.0,
+            depth: 1.0,
             material_indices: CuboidMaterialIndices {
                 front: 1,
                 back: 1,
@@ -200,33 +201,17 @@ fn create_beveled_pillar(origin: DVec3) -> Brushlet {
         BrushletSettings {
             name: "Stem".to_string(),
             operation: BooleanOp::Union,
-            knives: vec![
-                // Front-right edge
-                Knife {
-                    normal: DVec3::new(1.0, 0.0, 1.0).normalize(),
-                    distance_from_origin: base_distance + (origin.x + origin.z) / sqrt2,
-                    material_index: 1,
-                },
-                // Front-left edge
-                Knife {
-                    normal: DVec3::new(-1.0, 0.0, 1.0).normalize(),
-                    distance_from_origin: base_distance + (-origin.x + origin.z) / sqrt2,
-                    material_index: 1,
-                },
-                // Back-right edge
-                Knife {
-                    normal: DVec3::new(1.0, 0.0, -1.0).normalize(),
-                    distance_from_origin: base_distance + (origin.x - origin.z) / sqrt2,
-                    material_index: 1,
-                },
-                // Back-left edge
-                Knife {
-                    normal: DVec3::new(-1.0, 0.0, -1.0).normalize(),
-                    distance_from_origin: base_distance + (-origin.x - origin.z) / sqrt2,
-                    material_index: 1,
-                },
-            ],
+            knives: vec![],
             inverted: false,
         },
-    )
+    );
+
+    // Chamfer the four vertical edges
+    stem.bevel(&Bevel {
+        edges: BevelEdges::Vertical,
+        distance: 0.1,
+        segments: 1,
+        material_index: 1,
+    })
+    .expect("the stem is built from planes")
 }
@@ -150,11 +150,11 @@ fn setup_system(
             name: "Room 1".to_string(),
             operation: BooleanOp::Subtract,
             // Cut the brushlet with a knife
-            knives: vec![Knife {
-                normal: DVec3::new(-1.0, -1.0, -1.0),
-                distance_from_origin: 4.0,
-                material_index: MyMaterials::ProtoGrey.into(),
-            }],
+            knives: vec![Knife::new(
+                DVec3::new(-1.0, -1.0, -1.0),
+                4.0,
+                MyMaterials::ProtoGrey.into(),
+            )],
             inverted: true,
         },
     ));
@@ -180,11 +180,11 @@ fn setup_system(
     brush.transform(DAffine3::from_translation(DVec3::new(-4.0, 0.0, -4.0)));
 
     // Cut at the brush level with a knife to cut both rooms at once
-    brush.settings.knives = vec![Knife {
-        normal: DVec3::new(1.0, 1.0, 0.0),
-        distance_from_origin: 4.0,
-        material_index: MyMaterials::ProtoGrey.into(),
-    }];
+    brush.settings.knives = vec![Knife::new(
+        DVec3::new(1.0, 1.0, 0.0),
+        4.0,
+        MyMaterials::ProtoGrey.into(),
+    )];
 
     // Spawn the brush mesh
     spawn_brush_meshes(&mut commands, &mut meshes, &proto_materials, &brush);
@@ -233,7 +233,10 @@ fn spawn_brush_meshes(
     proto_materials: &ProtoMaterials,
     brush: &Brush,
 ) {
-    let meshes_with_materials = brush.to_mesh_data().to_bevy_meshes();
+    let meshes_with_materials = brush
+        .to_mesh_data()
+        .to_batched_mesh(DEFAULT_WELD_TOLERANCE)
+        .to_bevy_meshes();
     for mesh_material_map in meshes_with_materials {
         let material = match mesh_material_map.1 {
             0 => proto_materials.grey.clone(),
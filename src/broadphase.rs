@@ -1,4 +1,4 @@
-use crate::polygon::Polygon;
+use crate::{polygon::Polygon, tolerance::Tolerance};
 use std::ops::{Add, Sub};
 
 #[cfg(feature = "bevy")]
@@ -26,11 +26,19 @@ impl Raycast {
     }
 
     pub fn cast_against_polygons(&self, polygons: &Vec<Polygon>) -> Option<RaycastResult> {
+        self.cast_against_polygons_with_tolerance(polygons, &Tolerance::default())
+    }
+
+    pub fn cast_against_polygons_with_tolerance(
+        &self,
+        polygons: &Vec<Polygon>,
+        tolerance: &Tolerance,
+    ) -> Option<RaycastResult> {
         let mut closest_result = None;
         let mut closest_distance = f64::INFINITY;
 
         for polygon in polygons {
-            if let Some(result) = self.cast_against_polygon(polygon) {
+            if let Some(result) = self.cast_against_polygon_with_tolerance(polygon, tolerance) {
                 if result.distance < closest_distance {
                     closest_distance = result.distance;
                     closest_result = Some(result);
@@ -41,31 +49,76 @@ impl Raycast {
         closest_result
     }
 
-    fn cast_against_polygon(&self, polygon: &Polygon) -> Option<RaycastResult> {
+    pub fn cast_against_polygon(&self, polygon: &Polygon) -> Option<RaycastResult> {
+        self.cast_against_polygon_with_tolerance(polygon, &Tolerance::default())
+    }
+
+    /// Casts against the plane of a polygon and keeps the hit if it lies on the
+    /// polygon, see [`Polygon::contains_point_with_tolerance`].
+    ///
+    /// Backfaces are ignored, only polygons facing towards the ray are hit.
+    pub fn cast_against_polygon_with_tolerance(
+        &self,
+        polygon: &Polygon,
+        tolerance: &Tolerance,
+    ) -> Option<RaycastResult> {
         let normal = polygon.surface.normal;
         let denominator = normal.dot(self.direction);
-
-        // Ignore backfaces: only consider polygons facing towards the ray
-        if denominator >= 0.0 {
+        if denominator >= 0.0 || polygon.vertices.is_empty() {
             return None;
         }
 
-        let t = (polygon.vertices[0].pos - self.origin).dot(normal) / denominator;
+        let distance = normal.dot(polygon.vertices[0].pos - self.origin) / denominator;
+        if distance < 0.0 {
+            return None;
+        }
+        let point = self.origin + self.direction * distance;
+        polygon
+            .contains_point_with_tolerance(point, tolerance)
+            .then_some(RaycastResult {
+                distance,
+                point,
+                normal,
+            })
+    }
 
-        if t < 0.0 {
+    /// Casts against a counterclockwise triangle with the Möller–Trumbore algorithm.
+    ///
+    /// Backfaces are ignored. Hits on an edge count, so a ray through the shared
+    /// edge of two triangles hits both.
+    pub fn cast_against_triangle(&self, a: DVec3, b: DVec3, c: DVec3) -> Option<RaycastResult> {
+        // Barycentric slack so rays through an edge don't slip between triangles
+        const EPSILON: f64 = 1e-9;
+
+        let edge1 = b - a;
+        let edge2 = c - a;
+        let p = self.direction.cross(edge2);
+        let determinant = edge1.dot(p);
+        if determinant <= 0.0 {
             return None;
         }
 
-        let point = self.origin + self.direction * t;
+        let inverse = 1.0 / determinant;
+        let to_origin = self.origin - a;
+        let u = to_origin.dot(p) * inverse;
+        if !(-EPSILON..=1.0 + EPSILON).contains(&u) {
+            return None;
+        }
+        let q = to_origin.cross(edge1);
+        let v = self.direction.dot(q) * inverse;
+        if v < -EPSILON || u + v > 1.0 + EPSILON {
+            return None;
+        }
 
-        if !polygon.contains_point(point) {
+        let distance = edge2.dot(q) * inverse;
+        if distance < 0.0 {
             return None;
         }
 
         Some(RaycastResult {
-            distance: t,
-            point,
-            normal,
+            distance,
+            point: self.origin + self.direction * distance,
+            normal: edge1.cross(edge2).normalize(),
         })
     }
 
@@ -90,17 +143,17 @@ impl Raycast {
 
         let mut normal = DVec3::ZERO;
         if t_enter == t1.x {
-            normal.x = -1.0 * inv_direction.x.signum();
+            normal.x = -inv_direction.x.signum();
         } else if t_enter == t2.x {
-            normal.x = 1.0 * inv_direction.x.signum();
+            normal.x = inv_direction.x.signum();
         } else if t_enter == t1.y {
-            normal.y = -1.0 * inv_direction.y.signum();
+            normal.y = -inv_direction.y.signum();
         } else if t_enter == t2.y {
-            normal.y = 1.0 * inv_direction.y.signum();
+            normal.y = inv_direction.y.signum();
         } else if t_enter == t1.z {
-            normal.z = -1.0 * inv_direction.z.signum();
+            normal.z = -inv_direction.z.signum();
         } else if t_enter == t2.z {
-            normal.z = 1.0 * inv_direction.z.signum();
+            normal.z = inv_direction.z.signum();
         }
 
         Some(RaycastResult {
@@ -112,6 +165,7 @@ impl Raycast {
 }
 
 #[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[cfg_attr(feature = "bevy", derive(bevy::prelude::Reflect))]
 pub struct Aabb {
     pub min: DVec3,
@@ -196,6 +250,10 @@ impl Aabb {
 #[cfg(test)]
 mod tests {
     use super::{Aabb, Raycast};
+    use crate::{
+        polygon::{Polygon, Vertex},
+        surface::Surface,
+    };
 
     #[cfg(feature = "bevy")]
     use bevy::math::DVec3;
@@ -254,4 +312,50 @@ mod tests {
         let result = raycast.cast_against_aabb(&aabb);
         assert!(result.is_none());
     }
+
+    #[test]
+    fn test_raycast_triangle() {
+        let [a, b, c] = [DVec3::ZERO, DVec3::X, DVec3::Y];
+        let raycast = Raycast::new(DVec3::new(0.25, 0.25, 1.0), -DVec3::Z);
+        let result = raycast.cast_against_triangle(a, b, c).unwrap();
+        assert_eq!(result.distance, 1.0);
+        assert_eq!(result.point, DVec3::new(0.25, 0.25, 0.0));
+        assert_eq!(result.normal, DVec3::Z);
+
+        // Backface and outside the triangle
+        assert!(raycast.cast_against_triangle(a, c, b).is_none());
+        let outside = Raycast::new(DVec3::new(0.75, 0.75, 1.0), -DVec3::Z);
+        assert!(outside.cast_against_triangle(a, b, c).is_none());
+    }
+
+    #[test]
+    fn test_polygon_contains_point() {
+        // An L shape, the notch at the top right is outside
+        let vertices = [
+            (0.0, 0.0),
+            (2.0, 0.0),
+            (2.0, 1.0),
+            (1.0, 1.0),
+            (1.0, 2.0),
+            (0.0, 2.0),
+        ]
+        .map(|(x, y)| Vertex::new(DVec3::new(x, y, 0.0), DVec3::Z));
+        let polygon = Polygon {
+            vertices: vertices.to_vec(),
+            surface: Surface::new(DVec3::Z, 0.0, 0),
+        };
+        assert!(polygon.contains_point(DVec3::new(0.5, 1.5, 0.0)));
+        assert!(polygon.contains_point(DVec3::new(1.5, 0.5, 0.0)));
+        assert!(polygon.contains_point(DVec3::new(1.0, 1.5, 0.0)));
+        assert!(!polygon.contains_point(DVec3::new(1.5, 1.5, 0.0)));
+        assert!(!polygon.contains_point(DVec3::new(0.5, 0.5, 1.0)));
+
+        let raycast = Raycast::new(DVec3::new(1.5, 1.5, 1.0), -DVec3::Z);
+        assert!(raycast.cast_against_polygon(&polygon).is_none());
+        let raycast = Raycast::new(DVec3::new(0.5, 1.5, 1.0), -DVec3::Z);
+        assert_eq!(
+            raycast.cast_against_polygon(&polygon).unwrap().distance,
+            1.0
+        );
+    }
 }
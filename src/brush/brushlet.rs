@@ -1,18 +1,32 @@
-use super::{node::Node, operations::Knife, BooleanOp, MeshData};
+use std::collections::HashMap;
+
+use super::{
+    node::Node,
+    operations::{Bevel, Hollow, Knife},
+    BooleanOp, Brush, BrushError, MeshData,
+};
 use crate::{
     broadphase::{Aabb, Raycast, RaycastResult},
     polygon::{Polygon, Vertex},
-    primitives::Cuboid,
+    primitives::{
+        Capsule, Cone, Cuboid, Cylinder, Extrusion, Pyramid, Sphere, SphereTessellation, Wedge,
+    },
     surface::Surface,
+    tolerance::Tolerance,
+    util::{
+        clean_outline_2d, generate_faces, generate_polygons_from_surfaces, is_convex_2d,
+        merge_coplanar_polygons, prism_surfaces,
+    },
 };
 
 #[cfg(feature = "bevy")]
-use bevy::math::{dvec3, DAffine3, DQuat, DVec3};
+use bevy::math::{dvec3, DAffine3, DQuat, DVec2, DVec3};
 
 #[cfg(not(feature = "bevy"))]
-use glam::{dvec3, DAffine3, DQuat, DVec3};
+use glam::{dvec3, DAffine3, DQuat, DVec2, DVec3};
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[cfg_attr(feature = "bevy", derive(bevy::prelude::Reflect))]
 pub struct BrushletSettings {
     pub name: String,
@@ -28,38 +42,53 @@ pub struct BrushletSettings {
 ///
 /// # Fields
 /// * `polygons` - The polygons that make up the brushlet
-/// * `operation` - The boolean operation to perform
-/// * `knives` - The knives to use for cutting
-/// * `inverted` - Whether the brushlet is inverted
+/// * `surfaces` - The planes defining the brushlet, if it is a convex solid built from planes
+/// * `aabb` - The bounding box of the polygons
+/// * `settings` - The name, operation, knives and inverted flag of the brushlet
+///
+/// When `surfaces` is set it is the canonical representation of the brushlet and
+/// `polygons` is regenerated from it whenever the brushlet changes.
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[cfg_attr(feature = "bevy", derive(bevy::prelude::Reflect))]
 pub struct Brushlet {
     pub polygons: Vec<Polygon>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub surfaces: Option<Vec<Surface>>,
     pub aabb: Aabb,
     pub settings: BrushletSettings,
 }
 
 impl Brushlet {
     pub fn union(&self, other: &Brushlet) -> Self {
-        let mut a = Node::new(self.polygons.clone());
-        let mut b = Node::new(other.polygons.clone());
+        self.union_with_tolerance(other, &Tolerance::default())
+    }
+
+    pub fn union_with_tolerance(&self, other: &Brushlet, tolerance: &Tolerance) -> Self {
+        let mut a = Node::new(self.polygons.clone(), *tolerance);
+        let mut b = Node::new(other.polygons.clone(), *tolerance);
         a.clip_to(&b);
         b.clip_to(&a);
         b.invert();
         b.clip_to(&a);
         b.invert();
-        let mut a = a;
         a.build(b.all_polygons());
+        let polygons = merge_coplanar_polygons(a.all_polygons(), tolerance);
         Brushlet {
-            polygons: a.all_polygons(),
+            aabb: Aabb::from(&polygons),
+            polygons,
+            surfaces: None,
             settings: self.settings.clone(),
-            aabb: Aabb::from(&a.all_polygons()),
         }
     }
 
     pub fn subtract(&self, other: &Brushlet) -> Self {
-        let mut a = Node::new(self.polygons.clone());
-        let mut b = Node::new(other.polygons.clone());
+        self.subtract_with_tolerance(other, &Tolerance::default())
+    }
+
+    pub fn subtract_with_tolerance(&self, other: &Brushlet, tolerance: &Tolerance) -> Self {
+        let mut a = Node::new(self.polygons.clone(), *tolerance);
+        let mut b = Node::new(other.polygons.clone(), *tolerance);
         a.invert();
         a.clip_to(&b);
         b.clip_to(&a);
@@ -68,16 +97,22 @@ impl Brushlet {
         b.invert();
         a.build(b.all_polygons());
         a.invert();
+        let polygons = merge_coplanar_polygons(a.all_polygons(), tolerance);
         Brushlet {
-            polygons: a.all_polygons(),
+            aabb: Aabb::from(&polygons),
+            polygons,
+            surfaces: None,
             settings: self.settings.clone(),
-            aabb: Aabb::from(&a.all_polygons()),
         }
     }
 
     pub fn intersect(&self, other: &Brushlet) -> Self {
-        let mut a = Node::new(self.polygons.clone());
-        let mut b = Node::new(other.polygons.clone());
+        self.intersect_with_tolerance(other, &Tolerance::default())
+    }
+
+    pub fn intersect_with_tolerance(&self, other: &Brushlet, tolerance: &Tolerance) -> Self {
+        let mut a = Node::new(self.polygons.clone(), *tolerance);
+        let mut b = Node::new(other.polygons.clone(), *tolerance);
         a.invert();
         b.clip_to(&a);
         b.invert();
@@ -85,18 +120,24 @@ impl Brushlet {
         b.clip_to(&a);
         a.build(b.all_polygons());
         a.invert();
+        let polygons = merge_coplanar_polygons(a.all_polygons(), tolerance);
         Brushlet {
-            polygons: a.all_polygons(),
+            aabb: Aabb::from(&polygons),
+            polygons,
+            surfaces: None,
             settings: self.settings.clone(),
-            aabb: Aabb::from(&a.all_polygons()),
         }
     }
 
     pub fn to_mesh_data(&self) -> MeshData {
+        self.to_mesh_data_with_tolerance(&Tolerance::default())
+    }
+
+    pub fn to_mesh_data_with_tolerance(&self, tolerance: &Tolerance) -> MeshData {
         let mut final_brushlet = self.clone();
 
         for knife in &self.settings.knives {
-            final_brushlet = knife.perform(&final_brushlet);
+            final_brushlet = knife.perform_with_tolerance(&final_brushlet, tolerance);
         }
 
         if self.settings.inverted {
@@ -110,6 +151,7 @@ impl Brushlet {
     pub fn inverse(&self) -> Self {
         let mut csg = Brushlet {
             polygons: self.polygons.clone(),
+            surfaces: None,
             settings: self.settings.clone(),
             aabb: self.aabb,
         };
@@ -120,22 +162,250 @@ impl Brushlet {
     }
 
     pub fn try_select(&self, raycast: &Raycast) -> Option<RaycastResult> {
+        self.try_select_with_tolerance(raycast, &Tolerance::default())
+    }
+
+    /// Like [`Brushlet::try_select`], with the tolerance used to decide whether
+    /// a hit lies on a polygon.
+    pub fn try_select_with_tolerance(
+        &self,
+        raycast: &Raycast,
+        tolerance: &Tolerance,
+    ) -> Option<RaycastResult> {
         if raycast.cast_against_aabb(&self.aabb).is_some() {
-            if let Some(result) = raycast.cast_against_polygons(&self.polygons) {
+            if let Some(result) =
+                raycast.cast_against_polygons_with_tolerance(&self.polygons, tolerance)
+            {
                 return Some(result);
             }
         }
         None
     }
 
+    /// Creates a convex brushlet from the planes that bound it.
+    ///
+    /// The planes are kept as the canonical representation of the brushlet,
+    /// see [`Brushlet::regenerate_polygons`].
     pub fn from_surfaces(surfaces: Vec<Surface>, settings: BrushletSettings) -> Self {
-        let polygons = crate::util::generate_polygons_from_surfaces(&surfaces);
+        Self::from_surfaces_with_tolerance(surfaces, settings, &Tolerance::default())
+    }
+
+    /// Like [`Brushlet::from_surfaces`], with the tolerance used to find the corners.
+    pub fn from_surfaces_with_tolerance(
+        surfaces: Vec<Surface>,
+        settings: BrushletSettings,
+        tolerance: &Tolerance,
+    ) -> Self {
+        let polygons = generate_polygons_from_surfaces(&surfaces, tolerance);
         let aabb = Aabb::from(&polygons);
         Self {
             polygons,
+            surfaces: Some(surfaces),
+            settings,
+            aabb,
+        }
+    }
+
+    /// Like [`Brushlet::from_surfaces`], but fails if the planes don't bound a
+    /// closed solid where every plane is a face.
+    pub fn try_from_surfaces(
+        surfaces: Vec<Surface>,
+        settings: BrushletSettings,
+    ) -> Result<Self, BrushError> {
+        Self::try_from_surfaces_with_tolerance(surfaces, settings, &Tolerance::default())
+    }
+
+    /// Like [`Brushlet::try_from_surfaces`], with the tolerance used to find the corners.
+    pub fn try_from_surfaces_with_tolerance(
+        surfaces: Vec<Surface>,
+        settings: BrushletSettings,
+        tolerance: &Tolerance,
+    ) -> Result<Self, BrushError> {
+        let polygons = validate_surfaces(&surfaces, tolerance)?;
+        let aabb = Aabb::from(&polygons);
+        Ok(Self {
+            polygons,
+            surfaces: Some(surfaces),
             settings,
             aabb,
+        })
+    }
+
+    /// Checks the brushlet for degenerate geometry.
+    ///
+    /// Plane defined brushlets get the checks of [`Brushlet::try_from_surfaces`],
+    /// baked brushlets need at least one polygon and every polygon must pass
+    /// [`Polygon::try_new`].
+    pub fn validate(&self) -> Result<(), BrushError> {
+        self.validate_with_tolerance(&Tolerance::default())
+    }
+
+    /// Like [`Brushlet::validate`], with the tolerance used to check the planes
+    /// or polygons.
+    pub fn validate_with_tolerance(&self, tolerance: &Tolerance) -> Result<(), BrushError> {
+        if let Some(surfaces) = &self.surfaces {
+            return validate_surfaces(surfaces, tolerance).map(|_| ());
+        }
+        if self.polygons.is_empty() {
+            return Err(BrushError::EmptySolid);
+        }
+        for polygon in &self.polygons {
+            Polygon::try_new_with_tolerance(
+                polygon.vertices.clone(),
+                polygon.surface.material_idx,
+                tolerance,
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Whether the brushlet is defined by planes rather than baked polygons.
+    pub fn is_plane_defined(&self) -> bool {
+        self.surfaces.is_some()
+    }
+
+    /// Rebuilds the polygons and bounding box from the defining planes.
+    ///
+    /// Does nothing if the brushlet only has baked polygons.
+    pub fn regenerate_polygons(&mut self) {
+        self.regenerate_polygons_with_tolerance(&Tolerance::default());
+    }
+
+    /// Like [`Brushlet::regenerate_polygons`], with the tolerance used to find the corners.
+    pub fn regenerate_polygons_with_tolerance(&mut self, tolerance: &Tolerance) {
+        if let Some(surfaces) = &self.surfaces {
+            self.polygons = generate_polygons_from_surfaces(surfaces, tolerance);
+            self.aabb = Aabb::from(&self.polygons);
+        }
+    }
+
+    /// Bevels or chamfers edges of the brushlet, see [`Bevel`].
+    pub fn bevel(&self, bevel: &Bevel) -> Result<Brushlet, BrushError> {
+        bevel.perform(self)
+    }
+
+    /// Turns the brushlet into a brush of walls around its volume, see [`Hollow`].
+    pub fn hollow(&self, hollow: &Hollow) -> Result<Brush, BrushError> {
+        hollow.perform(self)
+    }
+
+    /// Finds the face, an index into the brushlet's planes, hit by a raycast.
+    pub fn face_from_raycast(&self, raycast_result: &RaycastResult) -> Option<usize> {
+        self.face_from_raycast_with_tolerance(raycast_result, &Tolerance::default())
+    }
+
+    /// Like [`Brushlet::face_from_raycast`], hit points within ten times the
+    /// tolerance's epsilon of a plane count as on it.
+    pub fn face_from_raycast_with_tolerance(
+        &self,
+        raycast_result: &RaycastResult,
+        tolerance: &Tolerance,
+    ) -> Option<usize> {
+        let surfaces = self.surfaces.as_ref()?;
+        surfaces
+            .iter()
+            .enumerate()
+            .filter(|(_, surface)| {
+                (surface.normal.dot(raycast_result.point) - surface.distance_from_origin).abs()
+                    < tolerance.epsilon * 10.0
+            })
+            // Points on an edge lie on several planes, prefer the one facing the hit normal
+            .max_by(|(_, a), (_, b)| {
+                a.normal
+                    .dot(raycast_result.normal)
+                    .total_cmp(&b.normal.dot(raycast_result.normal))
+            })
+            .map(|(idx, _)| idx)
+    }
+
+    /// Moves a face along its normal by `distance`, negative values move it inwards.
+    ///
+    /// The neighbouring faces stretch to follow it and every face keeps its
+    /// material. Faces that no longer touch the solid are removed, which shifts
+    /// the indices of the faces after them. The brushlet is left untouched if
+    /// the move fails.
+    pub fn move_face(&mut self, face_idx: usize, distance: f64) -> Result<(), BrushError> {
+        self.move_face_with_tolerance(face_idx, distance, &Tolerance::default())
+    }
+
+    /// Like [`Brushlet::move_face`], with the tolerance used to find the corners.
+    pub fn move_face_with_tolerance(
+        &mut self,
+        face_idx: usize,
+        distance: f64,
+        tolerance: &Tolerance,
+    ) -> Result<(), BrushError> {
+        let mut surfaces = self.surfaces.clone().ok_or(BrushError::NotPlaneDefined)?;
+        let surface = surfaces
+            .get_mut(face_idx)
+            .ok_or(BrushError::FaceAtIndexDoesNotExist(face_idx))?;
+        surface.distance_from_origin += distance * surface.normal.length();
+
+        let faces = generate_faces(&surfaces, tolerance);
+        let (surfaces, polygons): (Vec<Surface>, Vec<Polygon>) = surfaces
+            .into_iter()
+            .zip(faces)
+            .filter_map(|(surface, face)| face.map(|polygon| (surface, polygon)))
+            .unzip();
+        if polygons.len() < 4 {
+            return Err(BrushError::EmptySolid);
+        }
+        self.aabb = Aabb::from(&polygons);
+        self.polygons = polygons;
+        self.surfaces = Some(surfaces);
+        Ok(())
+    }
+
+    /// Creates a new brushlet by sweeping a face outwards along its normal by `distance`.
+    ///
+    /// The new brushlet uses the face's material on all of its faces and the
+    /// given settings, so it can be unioned onto the brushlet.
+    pub fn extrude_face(
+        &self,
+        face_idx: usize,
+        distance: f64,
+        settings: BrushletSettings,
+    ) -> Result<Brushlet, BrushError> {
+        self.extrude_face_with_tolerance(face_idx, distance, settings, &Tolerance::default())
+    }
+
+    /// Like [`Brushlet::extrude_face`], with the tolerance used to find the face's
+    /// polygon and the corners of the new brushlet.
+    pub fn extrude_face_with_tolerance(
+        &self,
+        face_idx: usize,
+        distance: f64,
+        settings: BrushletSettings,
+        tolerance: &Tolerance,
+    ) -> Result<Brushlet, BrushError> {
+        let surfaces = self.surfaces.as_ref().ok_or(BrushError::NotPlaneDefined)?;
+        let surface = surfaces
+            .get(face_idx)
+            .ok_or(BrushError::FaceAtIndexDoesNotExist(face_idx))?;
+        if distance <= 0.0 {
+            return Err(BrushError::EmptySolid);
         }
+        let polygon = self
+            .polygons
+            .iter()
+            .find(|polygon| tolerance.same_plane(&polygon.surface, surface))
+            .ok_or(BrushError::DegeneratePolygon)?;
+
+        let outline: Vec<DVec3> = polygon.positions();
+        let material_idx = surface.material_idx;
+        let mut surfaces = prism_surfaces(
+            &outline,
+            surface.normal.normalize() * distance,
+            &vec![material_idx; outline.len()],
+            (material_idx, material_idx),
+        );
+        // The cap keeps the texture of the face it was pulled from
+        let end = surfaces.len() - 1;
+        surfaces[end].texture = surface.texture;
+
+        Ok(Brushlet::from_surfaces_with_tolerance(
+            surfaces, settings, tolerance,
+        ))
     }
 
     pub fn compute_transform(&self) -> DAffine3 {
@@ -161,26 +431,46 @@ impl Brushlet {
         DAffine3::from_scale_rotation_translation(avg_scale, avg_rotation, avg_translation)
     }
 
+    /// Transforms the brushlet. Plane defined brushlets transform their
+    /// planes and regenerate their polygons from them.
     pub fn transform(&self, transform: DAffine3) -> Self {
-        let mut polygons = Vec::new();
-        for polygon in &self.polygons {
-            polygons.push(polygon.transform(transform));
-        }
+        self.transform_with_tolerance(transform, &Tolerance::default())
+    }
+
+    /// Like [`Brushlet::transform`], with the tolerance used to regenerate
+    /// plane defined brushlets.
+    pub fn transform_with_tolerance(&self, transform: DAffine3, tolerance: &Tolerance) -> Self {
         let mut knives = Vec::new();
         for knife in &self.settings.knives {
             knives.push(knife.transform(transform));
         }
 
+        let settings = BrushletSettings {
+            name: self.settings.name.clone(),
+            operation: self.settings.operation,
+            knives,
+            inverted: self.settings.inverted,
+        };
+
+        if let Some(surfaces) = &self.surfaces {
+            let surfaces = surfaces
+                .iter()
+                .map(|surface| surface.transform(transform))
+                .collect();
+            return Brushlet::from_surfaces_with_tolerance(surfaces, settings, tolerance);
+        }
+
+        let mut polygons = Vec::new();
+        for polygon in &self.polygons {
+            polygons.push(polygon.transform(transform));
+        }
+
         let aabb = Aabb::from(&polygons);
 
         Brushlet {
             polygons,
-            settings: BrushletSettings {
-                name: self.settings.name.clone(),
-                operation: self.settings.operation,
-                knives,
-                inverted: self.settings.inverted,
-            },
+            surfaces: None,
+            settings,
             aabb,
         }
     }
@@ -190,7 +480,7 @@ impl Brushlet {
         let half_height = cuboid.height * 0.5;
         let half_depth = cuboid.depth * 0.5;
 
-        let vertices = vec![
+        let vertices = [
             // Define vertices without normals initially
             Vertex::new(
                 cuboid.origin + dvec3(-half_width, -half_height, -half_depth),
@@ -284,19 +574,576 @@ impl Brushlet {
         ];
 
         let aabb = Aabb::from(&polygons);
+        let surfaces = polygons.iter().map(|polygon| polygon.surface).collect();
         Brushlet {
             polygons,
+            surfaces: Some(surfaces),
+            settings,
+            aabb,
+        }
+    }
+
+    /// Creates a brushlet from a cylinder.
+    ///
+    /// The corners of the sides lie on the circle of the cylinder's radius.
+    pub fn from_cylinder(cylinder: Cylinder, settings: BrushletSettings) -> Self {
+        let axis = cylinder.axis.normalize();
+        let half_height = cylinder.height * 0.5;
+
+        let mut surfaces = side_surfaces(
+            cylinder.origin,
+            axis,
+            cylinder.height,
+            cylinder.segments,
+            (cylinder.radius, cylinder.radius),
+            cylinder.material_indices.sides,
+        );
+        surfaces.push(Surface::new(
+            axis,
+            axis.dot(cylinder.origin) + half_height,
+            cylinder.material_indices.top,
+        ));
+        surfaces.push(Surface::new(
+            -axis,
+            -axis.dot(cylinder.origin) + half_height,
+            cylinder.material_indices.bottom,
+        ));
+
+        Self::from_surfaces(surfaces, settings)
+    }
+
+    /// Creates a brushlet from a cone, or a frustum when it has a top radius.
+    ///
+    /// The corners of the sides lie on the circles of the cone's radii.
+    pub fn from_cone(cone: Cone, settings: BrushletSettings) -> Self {
+        let axis = cone.axis.normalize();
+        let half_height = cone.height * 0.5;
+        let top_radius = cone.top_radius.unwrap_or(0.0);
+
+        let mut surfaces = side_surfaces(
+            cone.origin,
+            axis,
+            cone.height,
+            cone.segments,
+            (cone.radius, top_radius),
+            cone.material_indices.sides,
+        );
+        if top_radius > 0.0 {
+            surfaces.push(Surface::new(
+                axis,
+                axis.dot(cone.origin) + half_height,
+                cone.material_indices.top,
+            ));
+        }
+        surfaces.push(Surface::new(
+            -axis,
+            -axis.dot(cone.origin) + half_height,
+            cone.material_indices.bottom,
+        ));
+
+        Self::from_surfaces(surfaces, settings)
+    }
+
+    /// Creates a brushlet from a pyramid.
+    pub fn from_pyramid(pyramid: Pyramid, settings: BrushletSettings) -> Self {
+        let half_width = pyramid.width * 0.5;
+        let half_height = pyramid.height * 0.5;
+        let half_depth = pyramid.depth * 0.5;
+        let materials = &pyramid.material_indices;
+
+        let mut surfaces: Vec<Surface> = [
+            (DVec3::Z, half_depth, materials.front),
+            (-DVec3::Z, half_depth, materials.back),
+            (DVec3::X, half_width, materials.right),
+            (-DVec3::X, half_width, materials.left),
+        ]
+        .into_iter()
+        .map(|(direction, half_extent, material_idx)| {
+            // Slopes from the base edge up to the apex
+            let normal = (direction * pyramid.height + DVec3::Y * half_extent).normalize();
+            let point = pyramid.origin + direction * half_extent - DVec3::Y * half_height;
+            Surface::new(normal, normal.dot(point), material_idx)
+        })
+        .collect();
+        surfaces.push(Surface::new(
+            -DVec3::Y,
+            -pyramid.origin.y + half_height,
+            materials.bottom,
+        ));
+
+        Self::from_surfaces(surfaces, settings)
+    }
+
+    /// Creates a brushlet from a wedge.
+    pub fn from_wedge(wedge: Wedge, settings: BrushletSettings) -> Self {
+        let half_width = wedge.width * 0.5;
+        let half_height = wedge.height * 0.5;
+        let half_depth = wedge.depth * 0.5;
+        let materials = &wedge.material_indices;
+        let origin = wedge.origin;
+
+        // Runs from the bottom front edge to the top back edge
+        let slope_normal = DVec3::new(0.0, wedge.depth, wedge.height).normalize();
+
+        let surfaces = vec![
+            Surface::new(slope_normal, slope_normal.dot(origin), materials.slope),
+            Surface::new(-DVec3::Z, -origin.z + half_depth, materials.back),
+            Surface::new(-DVec3::Y, -origin.y + half_height, materials.bottom),
+            Surface::new(DVec3::X, origin.x + half_width, materials.right),
+            Surface::new(-DVec3::X, -origin.x + half_width, materials.left),
+        ];
+
+        Self::from_surfaces(surfaces, settings)
+    }
+
+    /// Creates a brushlet from a sphere.
+    pub fn from_sphere(sphere: Sphere, settings: BrushletSettings) -> Self {
+        match sphere.tessellation {
+            SphereTessellation::Uv { segments, rings } => {
+                let rings = rings.max(2);
+                let profile: Vec<(f64, f64)> = (0..=rings)
+                    .map(|i| {
+                        let angle = std::f64::consts::PI * i as f64 / rings as f64;
+                        (angle.sin() * sphere.radius, angle.cos() * sphere.radius)
+                    })
+                    .collect();
+                let (points, faces) = revolve_profile(
+                    sphere.origin,
+                    DVec3::Y,
+                    &profile,
+                    segments,
+                    sphere.radius * Surface::EPSILON,
+                );
+                let faces = faces
+                    .into_iter()
+                    .map(|face| (face, sphere.material_idx))
+                    .collect();
+                Self::from_faces(&points, faces, sphere.origin, settings)
+            }
+            SphereTessellation::Geodesic { subdivisions } => {
+                let (directions, faces) = geodesic_sphere(subdivisions);
+                let points: Vec<DVec3> = directions
+                    .into_iter()
+                    .map(|direction| sphere.origin + direction * sphere.radius)
+                    .collect();
+                let faces = faces
+                    .into_iter()
+                    .map(|face| (face, sphere.material_idx))
+                    .collect();
+                Self::from_faces(&points, faces, sphere.origin, settings)
+            }
+        }
+    }
+
+    /// Creates a brushlet from a capsule.
+    pub fn from_capsule(capsule: Capsule, settings: BrushletSettings) -> Self {
+        let axis = capsule.axis.normalize();
+        let rings = capsule.rings.max(1);
+        let half_length = (capsule.height * 0.5 - capsule.radius).max(0.0);
+
+        // From the top pole down to the equator, then from the equator down to the bottom pole
+        let mut profile = Vec::with_capacity(rings * 2 + 2);
+        for i in 0..=rings {
+            let angle = std::f64::consts::FRAC_PI_2 * i as f64 / rings as f64;
+            profile.push((
+                angle.sin() * capsule.radius,
+                half_length + angle.cos() * capsule.radius,
+            ));
+        }
+        for i in 0..=rings {
+            let angle = std::f64::consts::FRAC_PI_2 * (1.0 + i as f64 / rings as f64);
+            profile.push((
+                angle.sin() * capsule.radius,
+                -half_length + angle.cos() * capsule.radius,
+            ));
+        }
+        if half_length == 0.0 {
+            // The equator would otherwise be a flat band of zero height
+            profile.remove(rings + 1);
+        }
+
+        let (points, faces) = revolve_profile(
+            capsule.origin,
+            axis,
+            &profile,
+            capsule.segments,
+            capsule.radius * Surface::EPSILON,
+        );
+        let faces = faces
+            .into_iter()
+            .map(|face| (face, capsule.material_idx))
+            .collect();
+        Self::from_faces(&points, faces, capsule.origin, settings)
+    }
+
+    /// Creates a brushlet from an extrusion with a convex outline.
+    ///
+    /// Use [`Brush::from_extrusion`](super::Brush::from_extrusion) for concave outlines.
+    pub fn from_extrusion(
+        extrusion: Extrusion,
+        settings: BrushletSettings,
+    ) -> Result<Self, BrushError> {
+        Self::from_extrusion_with_tolerance(extrusion, settings, &Tolerance::default())
+    }
+
+    /// Like [`Brushlet::from_extrusion`], with the tolerance used to clean up
+    /// the outline and find the corners.
+    pub fn from_extrusion_with_tolerance(
+        extrusion: Extrusion,
+        settings: BrushletSettings,
+        tolerance: &Tolerance,
+    ) -> Result<Self, BrushError> {
+        let outline = clean_outline_2d(&extrusion.outline, tolerance);
+        if outline.len() < 3 || extrusion.extrusion.length_squared() == 0.0 {
+            return Err(BrushError::DegeneratePolygon);
+        }
+        if !is_convex_2d(&outline, tolerance) {
+            return Err(BrushError::NonConvex);
+        }
+        Ok(Self::from_convex_extrusion(
+            &extrusion, &outline, settings, tolerance,
+        ))
+    }
+
+    /// Creates a brushlet from a clean, convex piece of an extrusion's outline.
+    pub(crate) fn from_convex_extrusion(
+        extrusion: &Extrusion,
+        outline: &[DVec2],
+        settings: BrushletSettings,
+        tolerance: &Tolerance,
+    ) -> Self {
+        let materials = &extrusion.material_indices;
+        let outline: Vec<DVec3> = outline
+            .iter()
+            .map(|point| extrusion.to_world(*point))
+            .collect();
+        Self::from_surfaces_with_tolerance(
+            prism_surfaces(
+                &outline,
+                extrusion.extrusion,
+                &vec![materials.sides; outline.len()],
+                (materials.start, materials.end),
+            ),
+            settings,
+            tolerance,
+        )
+    }
+
+    /// Creates a brushlet from the faces of a convex hull around `center`.
+    ///
+    /// Each face is a list of point indices and its material index. The polygons
+    /// are built directly from the faces, the winding of each face is fixed to
+    /// point away from `center`.
+    pub(crate) fn from_faces(
+        points: &[DVec3],
+        faces: Vec<(Vec<usize>, usize)>,
+        center: DVec3,
+        settings: BrushletSettings,
+    ) -> Self {
+        let polygons: Vec<Polygon> = faces
+            .into_iter()
+            .map(|(mut face, material_idx)| {
+                let mut surface = Surface::from_points(
+                    points[face[0]],
+                    points[face[1]],
+                    points[face[2]],
+                    material_idx,
+                );
+                let centroid =
+                    face.iter().map(|idx| points[*idx]).sum::<DVec3>() / face.len() as f64;
+                if surface.normal.dot(centroid - center) < 0.0 {
+                    face.reverse();
+                    surface.flip();
+                }
+                Polygon {
+                    vertices: face
+                        .into_iter()
+                        .map(|idx| Vertex::new(points[idx], surface.normal))
+                        .collect(),
+                    surface,
+                }
+            })
+            .collect();
+
+        let aabb = Aabb::from(&polygons);
+        let surfaces = polygons.iter().map(|polygon| polygon.surface).collect();
+        Brushlet {
+            polygons,
+            surfaces: Some(surfaces),
             settings,
             aabb,
         }
     }
 }
 
+/// Revolves a profile of `(radius, height)` points around an axis.
+///
+/// Returns the points and the faces between consecutive profile points. Profile
+/// points with a radius below `pole_tolerance` become a single pole point.
+fn revolve_profile(
+    origin: DVec3,
+    axis: DVec3,
+    profile: &[(f64, f64)],
+    segments: usize,
+    pole_tolerance: f64,
+) -> (Vec<DVec3>, Vec<Vec<usize>>) {
+    let (u, v) = axis_basis(axis);
+    let segments = segments.max(3);
+
+    let mut points = Vec::new();
+    // The indices of each profile point around the axis, a single index for poles
+    let mut rings: Vec<Vec<usize>> = Vec::with_capacity(profile.len());
+    for (radius, height) in profile {
+        let center = origin + axis * *height;
+        if *radius < pole_tolerance {
+            points.push(center);
+            rings.push(vec![points.len() - 1]);
+            continue;
+        }
+        let ring = (0..segments)
+            .map(|i| {
+                let angle = std::f64::consts::TAU * i as f64 / segments as f64;
+                points.push(center + (u * angle.cos() + v * angle.sin()) * *radius);
+                points.len() - 1
+            })
+            .collect();
+        rings.push(ring);
+    }
+
+    let mut faces = Vec::new();
+    for pair in rings.windows(2) {
+        let (upper, lower) = (&pair[0], &pair[1]);
+        for i in 0..segments {
+            let next = (i + 1) % segments;
+            let mut face = Vec::with_capacity(4);
+            for (ring, indices) in [(upper, [i, next]), (lower, [next, i])] {
+                if ring.len() == 1 {
+                    face.push(ring[0]);
+                } else {
+                    face.extend(indices.map(|idx| ring[idx]));
+                }
+            }
+            if face.len() >= 3 {
+                faces.push(face);
+            }
+        }
+    }
+
+    (points, faces)
+}
+
+/// The unit directions and triangles of an icosahedron, subdivided `subdivisions` times.
+fn geodesic_sphere(subdivisions: usize) -> (Vec<DVec3>, Vec<Vec<usize>>) {
+    let t = (1.0 + 5f64.sqrt()) * 0.5;
+    let mut points: Vec<DVec3> = [
+        (-1.0, t, 0.0),
+        (1.0, t, 0.0),
+        (-1.0, -t, 0.0),
+        (1.0, -t, 0.0),
+        (0.0, -1.0, t),
+        (0.0, 1.0, t),
+        (0.0, -1.0, -t),
+        (0.0, 1.0, -t),
+        (t, 0.0, -1.0),
+        (t, 0.0, 1.0),
+        (-t, 0.0, -1.0),
+        (-t, 0.0, 1.0),
+    ]
+    .into_iter()
+    .map(|(x, y, z)| DVec3::new(x, y, z).normalize())
+    .collect();
+    let mut faces: Vec<[usize; 3]> = vec![
+        [0, 11, 5],
+        [0, 5, 1],
+        [0, 1, 7],
+        [0, 7, 10],
+        [0, 10, 11],
+        [1, 5, 9],
+        [5, 11, 4],
+        [11, 10, 2],
+        [10, 7, 6],
+        [7, 1, 8],
+        [3, 9, 4],
+        [3, 4, 2],
+        [3, 2, 6],
+        [3, 6, 8],
+        [3, 8, 9],
+        [4, 9, 5],
+        [2, 4, 11],
+        [6, 2, 10],
+        [8, 6, 7],
+        [9, 8, 1],
+    ];
+
+    for _ in 0..subdivisions {
+        let mut midpoints = HashMap::new();
+        let mut midpoint = |a: usize, b: usize| {
+            *midpoints.entry((a.min(b), a.max(b))).or_insert_with(|| {
+                points.push(((points[a] + points[b]) * 0.5).normalize());
+                points.len() - 1
+            })
+        };
+        faces = faces
+            .into_iter()
+            .flat_map(|[a, b, c]| {
+                let ab = midpoint(a, b);
+                let bc = midpoint(b, c);
+                let ca = midpoint(c, a);
+                [[a, ab, ca], [b, bc, ab], [c, ca, bc], [ab, bc, ca]]
+            })
+            .collect();
+    }
+
+    (points, faces.into_iter().map(Vec::from).collect())
+}
+
+/// The side planes of a prism, cone or frustum around an axis.
+///
+/// `radii` are the bottom and top radii, the corners of the sides lie on those circles.
+fn side_surfaces(
+    origin: DVec3,
+    axis: DVec3,
+    height: f64,
+    segments: usize,
+    radii: (f64, f64),
+    material_idx: usize,
+) -> Vec<Surface> {
+    let (u, v) = axis_basis(axis);
+    let segments = segments.max(3);
+    let corner_to_apothem = (std::f64::consts::PI / segments as f64).cos();
+    let bottom_apothem = radii.0 * corner_to_apothem;
+    let top_apothem = radii.1 * corner_to_apothem;
+
+    (0..segments)
+        .map(|i| {
+            let angle = std::f64::consts::TAU * i as f64 / segments as f64;
+            let radial = u * angle.cos() + v * angle.sin();
+            let normal = (radial * height + axis * (bottom_apothem - top_apothem)).normalize();
+            let point = origin + radial * bottom_apothem - axis * height * 0.5;
+            Surface::new(normal, normal.dot(point), material_idx)
+        })
+        .collect()
+}
+
+/// Two unit vectors perpendicular to the axis and each other.
+///
+/// For the Y axis these are X and -Z, so radial faces line up with the world axes.
+fn axis_basis(axis: DVec3) -> (DVec3, DVec3) {
+    let reference = if axis.x.abs() < 0.9 {
+        DVec3::X
+    } else {
+        DVec3::Z
+    };
+    let u = (reference - axis * axis.dot(reference)).normalize();
+    (u, axis.cross(u))
+}
+
+/// Builds the faces of a plane defined solid, failing on anything that would
+/// be silently dropped or leave the solid open.
+fn validate_surfaces(
+    surfaces: &[Surface],
+    tolerance: &Tolerance,
+) -> Result<Vec<Polygon>, BrushError> {
+    let mut planes = Vec::with_capacity(surfaces.len());
+    for surface in surfaces {
+        if !surface.normal.is_finite() || !surface.distance_from_origin.is_finite() {
+            return Err(BrushError::NonFiniteInput);
+        }
+        let length = surface.normal.length();
+        if length < Tolerance::NORMAL_EPSILON {
+            return Err(BrushError::DegeneratePolygon);
+        }
+        planes.push(Surface {
+            normal: surface.normal / length,
+            distance_from_origin: surface.distance_from_origin / length,
+            ..*surface
+        });
+    }
+
+    let keys: Vec<[i64; 4]> = planes
+        .iter()
+        .map(|plane| tolerance.quantize_plane(plane))
+        .collect();
+    for j in 0..keys.len() {
+        if let Some(i) = keys[..j].iter().position(|key| *key == keys[j]) {
+            return Err(BrushError::DuplicatePlane(i, j));
+        }
+    }
+
+    // The solid is bounded unless some direction leaves every plane behind.
+    // The normals must span all three dimensions, and otherwise such a direction
+    // can be found along the edge between two planes
+    let normals: Vec<DVec3> = planes.iter().map(|plane| plane.normal).collect();
+    let spans = normals.iter().enumerate().any(|(i, a)| {
+        normals[i + 1..].iter().enumerate().any(|(j, b)| {
+            normals[i + j + 2..]
+                .iter()
+                .any(|c| a.cross(*b).dot(*c).abs() > Tolerance::NORMAL_EPSILON)
+        })
+    });
+    let escapes = |direction: DVec3| {
+        normals
+            .iter()
+            .all(|normal| normal.dot(direction) <= Tolerance::NORMAL_EPSILON)
+    };
+    let open = normals.iter().enumerate().any(|(i, a)| {
+        normals[i + 1..].iter().any(|b| {
+            a.cross(*b)
+                .try_normalize()
+                .is_some_and(|direction| escapes(direction) || escapes(-direction))
+        })
+    });
+    if !spans || open {
+        return Err(BrushError::UnboundedSolid);
+    }
+
+    let faces = generate_faces(&planes, tolerance);
+    if faces.iter().flatten().count() < 4 {
+        return Err(BrushError::EmptySolid);
+    }
+    if let Some(idx) = faces.iter().position(|face| face.is_none()) {
+        return Err(BrushError::RedundantPlane(idx));
+    }
+
+    // Keep the planes as given, only the comparisons above needed them normalized
+    Ok(faces
+        .into_iter()
+        .zip(surfaces)
+        .map(|(face, surface)| Polygon {
+            surface: *surface,
+            ..face.unwrap()
+        })
+        .collect())
+}
+
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
     use super::*;
     use crate::prelude::*;
 
+    pub(crate) fn test_settings() -> BrushletSettings {
+        BrushletSettings {
+            name: "Test".into(),
+            operation: BooleanOp::Union,
+            knives: Vec::new(),
+            inverted: false,
+        }
+    }
+
+    /// A cube with every face using material 0.
+    pub(crate) fn test_cuboid(origin: DVec3, size: f64) -> Brushlet {
+        Brushlet::from_cuboid(
+            Cuboid {
+                origin,
+                width: size,
+                height: size,
+                depth: size,
+                material_indices: CuboidMaterialIndices::default(),
+            },
+            test_settings(),
+        )
+    }
+
     #[test]
     fn test_try_select() {
         let brushlet = Brushlet::from_cuboid(
@@ -324,12 +1171,13 @@ mod tests {
 
         let raycast = Raycast::new(DVec3::new(0.0, 0.0, -2.0), DVec3::Z);
         let selection = brushlet.try_select(&raycast);
+        // The cube spans -0.5 to 0.5, the ray hits its front face
         assert!(
             selection
                 == Some(RaycastResult {
-                    distance: 2.0,
-                    normal: DVec3::Z,
-                    point: DVec3::new(0.0, 0.0, -1.0),
+                    distance: 1.5,
+                    normal: -DVec3::Z,
+                    point: DVec3::new(0.0, 0.0, -0.5),
                 })
         );
     }
@@ -361,6 +1209,913 @@ mod tests {
 
         let raycast = Raycast::new(DVec3::new(0.0, 0.0, 2.0), DVec3::Z);
         let selection = brushlet.try_select(&raycast);
-        assert!(selection == None);
+        assert!(selection.is_none());
+    }
+
+    #[test]
+    fn test_try_select_small_brushlet() {
+        let mut brush = Brush::new("Detail");
+        brush.brushlets.push(test_cuboid(DVec3::ZERO, 0.003));
+        let raycast = Raycast::new(DVec3::new(0.001, 0.001, -1.0), DVec3::Z);
+        assert_eq!(brush.try_select_brushlet(&raycast), Some(0));
+        assert!((brush.try_select(&raycast).unwrap().distance - 0.9985).abs() < 1e-12);
+
+        // Smaller than the default epsilon, with a brush tolerance to match
+        let size = 5e-6;
+        let tolerance = Tolerance::scaled(1e-3);
+        let surfaces = test_cuboid(DVec3::ZERO, size).surfaces.unwrap();
+        brush.brushlets = vec![Brushlet::from_surfaces_with_tolerance(
+            surfaces,
+            test_settings(),
+            &tolerance,
+        )];
+        brush.settings.tolerance = tolerance;
+        let raycast = Raycast::new(DVec3::new(0.0, 0.0, -1.0), DVec3::Z);
+        assert_eq!(brush.try_select_brushlet(&raycast), Some(0));
+        let beside = Raycast::new(DVec3::new(size, 0.0, -1.0), DVec3::Z);
+        assert_eq!(brush.try_select_brushlet(&beside), None);
+    }
+
+    #[test]
+    fn test_transform_keeps_planes() {
+        let brushlet = Brushlet::from_cuboid(
+            Cuboid {
+                origin: DVec3::ZERO,
+                width: 2.0,
+                height: 2.0,
+                depth: 2.0,
+                material_indices: CuboidMaterialIndices {
+                    front: 1,
+                    back: 2,
+                    left: 3,
+                    right: 4,
+                    top: 5,
+                    bottom: 6,
+                },
+            },
+            BrushletSettings {
+                name: "Test".into(),
+                operation: BooleanOp::Union,
+                knives: Vec::new(),
+                inverted: false,
+            },
+        );
+
+        let transform = DAffine3::from_scale_rotation_translation(
+            DVec3::new(2.0, 1.0, 1.0),
+            DQuat::from_rotation_y(std::f64::consts::FRAC_PI_2),
+            DVec3::new(10.0, 0.0, 0.0),
+        );
+        let transformed = brushlet.transform(transform);
+
+        assert!(transformed.is_plane_defined());
+        assert_eq!(transformed.surfaces.as_ref().unwrap().len(), 6);
+        assert_eq!(transformed.polygons.len(), 6);
+        assert!((transformed.aabb.min - DVec3::new(9.0, -1.0, -2.0)).length() < 1e-9);
+        assert!((transformed.aabb.max - DVec3::new(11.0, 1.0, 2.0)).length() < 1e-9);
+
+        let mut materials: Vec<usize> = transformed
+            .polygons
+            .iter()
+            .map(|polygon| polygon.surface.material_idx)
+            .collect();
+        materials.sort();
+        assert_eq!(materials, vec![1, 2, 3, 4, 5, 6]);
+    }
+
+    #[test]
+    fn test_from_cylinder() {
+        let material_indices = CylinderMaterialIndices {
+            sides: 0,
+            top: 1,
+            bottom: 2,
+        };
+
+        let brushlet = Brushlet::from_cylinder(
+            Cylinder {
+                origin: DVec3::new(1.0, 2.0, 3.0),
+                radius: 2.0,
+                height: 4.0,
+                segments: 8,
+                axis: DVec3::Y,
+                material_indices: material_indices.clone(),
+            },
+            test_settings(),
+        );
+        assert_eq!(brushlet.polygons.len(), 10);
+        for polygon in &brushlet.polygons {
+            let expected_len = match polygon.surface.material_idx {
+                0 => 4,
+                _ => 8,
+            };
+            assert_eq!(polygon.vertices.len(), expected_len);
+            for vertex in &polygon.vertices {
+                let local = vertex.pos - DVec3::new(1.0, 2.0, 3.0);
+                assert!((local.y.abs() - 2.0).abs() < 1e-9);
+                assert!((local.x.hypot(local.z) - 2.0).abs() < 1e-9);
+            }
+        }
+        let top = brushlet
+            .polygons
+            .iter()
+            .find(|polygon| polygon.surface.material_idx == 1)
+            .unwrap();
+        assert!(top.surface.normal.abs_diff_eq(DVec3::Y, 1e-9));
+
+        let brushlet = Brushlet::from_cylinder(
+            Cylinder {
+                origin: DVec3::ZERO,
+                radius: 1.0,
+                height: 2.0,
+                segments: 4,
+                axis: DVec3::X,
+                material_indices,
+            },
+            test_settings(),
+        );
+        assert_eq!(brushlet.polygons.len(), 6);
+        assert!(brushlet.aabb.max.abs_diff_eq(
+            DVec3::new(
+                1.0,
+                std::f64::consts::FRAC_1_SQRT_2,
+                std::f64::consts::FRAC_1_SQRT_2
+            ),
+            1e-9
+        ));
+    }
+
+    #[test]
+    fn test_from_cone() {
+        let cone = Cone {
+            origin: DVec3::ZERO,
+            radius: 2.0,
+            top_radius: None,
+            height: 2.0,
+            segments: 6,
+            axis: DVec3::Y,
+            material_indices: ConeMaterialIndices {
+                sides: 0,
+                top: 1,
+                bottom: 2,
+            },
+        };
+
+        let pointed = Brushlet::from_cone(cone.clone(), test_settings());
+        assert_eq!(pointed.polygons.len(), 7);
+        assert!((pointed.aabb.max.y - 1.0).abs() < 1e-9);
+        for polygon in pointed
+            .polygons
+            .iter()
+            .filter(|p| p.surface.material_idx == 0)
+        {
+            assert_eq!(polygon.vertices.len(), 3);
+            assert!(polygon
+                .vertices
+                .iter()
+                .any(|vertex| vertex.pos.abs_diff_eq(DVec3::Y, 1e-9)));
+        }
+
+        let frustum = Brushlet::from_cone(
+            Cone {
+                top_radius: Some(1.0),
+                ..cone
+            },
+            test_settings(),
+        );
+        assert_eq!(frustum.polygons.len(), 8);
+        for vertex in frustum.polygons.iter().flat_map(|p| p.vertices.iter()) {
+            let expected_radius = if vertex.pos.y > 0.0 { 1.0 } else { 2.0 };
+            assert!((vertex.pos.x.hypot(vertex.pos.z) - expected_radius).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn test_from_pyramid_and_wedge() {
+        let pyramid = Brushlet::from_pyramid(
+            Pyramid {
+                origin: DVec3::new(0.0, 1.0, 0.0),
+                width: 4.0,
+                height: 2.0,
+                depth: 2.0,
+                material_indices: PyramidMaterialIndices {
+                    front: 0,
+                    back: 1,
+                    left: 2,
+                    right: 3,
+                    bottom: 4,
+                },
+            },
+            test_settings(),
+        );
+        assert_eq!(pyramid.polygons.len(), 5);
+        assert!(pyramid
+            .aabb
+            .min
+            .abs_diff_eq(DVec3::new(-2.0, 0.0, -1.0), 1e-9));
+        assert!(pyramid
+            .aabb
+            .max
+            .abs_diff_eq(DVec3::new(2.0, 2.0, 1.0), 1e-9));
+        let front = pyramid
+            .polygons
+            .iter()
+            .find(|polygon| polygon.surface.material_idx == 0)
+            .unwrap();
+        assert!(front.surface.normal.z > 0.0 && front.surface.normal.y > 0.0);
+
+        let wedge = Brushlet::from_wedge(
+            Wedge {
+                origin: DVec3::ZERO,
+                width: 2.0,
+                height: 1.0,
+                depth: 4.0,
+                material_indices: WedgeMaterialIndices {
+                    slope: 0,
+                    back: 1,
+                    bottom: 2,
+                    left: 3,
+                    right: 4,
+                },
+            },
+            test_settings(),
+        );
+        assert_eq!(wedge.polygons.len(), 5);
+        assert!(wedge
+            .aabb
+            .min
+            .abs_diff_eq(DVec3::new(-1.0, -0.5, -2.0), 1e-9));
+        assert!(wedge.aabb.max.abs_diff_eq(DVec3::new(1.0, 0.5, 2.0), 1e-9));
+        let slope = wedge
+            .polygons
+            .iter()
+            .find(|polygon| polygon.surface.material_idx == 0)
+            .unwrap();
+        assert_eq!(slope.vertices.len(), 4);
+        assert!(slope
+            .vertices
+            .iter()
+            .all(|vertex| (vertex.pos.y + vertex.pos.z / 4.0).abs() < 1e-9));
+    }
+
+    #[test]
+    fn test_from_sphere() {
+        let origin = DVec3::new(1.0, 2.0, 3.0);
+
+        let uv = Brushlet::from_sphere(
+            Sphere {
+                origin,
+                radius: 2.0,
+                tessellation: SphereTessellation::Uv {
+                    segments: 8,
+                    rings: 4,
+                },
+                material_idx: 0,
+            },
+            test_settings(),
+        );
+        assert_eq!(uv.polygons.len(), 32);
+        assert!(uv.aabb.min.abs_diff_eq(origin - DVec3::splat(2.0), 1e-9));
+        assert!(uv.aabb.max.abs_diff_eq(origin + DVec3::splat(2.0), 1e-9));
+
+        let geodesic = Brushlet::from_sphere(
+            Sphere {
+                origin,
+                radius: 2.0,
+                tessellation: SphereTessellation::Geodesic { subdivisions: 1 },
+                material_idx: 0,
+            },
+            test_settings(),
+        );
+        assert_eq!(geodesic.polygons.len(), 80);
+
+        for brushlet in [&uv, &geodesic] {
+            let surfaces = brushlet.surfaces.as_ref().unwrap();
+            for polygon in &brushlet.polygons {
+                assert!(polygon.surface.normal.dot(polygon.vertices[0].pos - origin) > 0.0);
+                for vertex in &polygon.vertices {
+                    assert!(((vertex.pos - origin).length() - 2.0).abs() < 1e-9);
+                    assert!(surfaces.iter().all(|surface| {
+                        surface.normal.dot(vertex.pos)
+                            <= surface.distance_from_origin + Surface::EPSILON
+                    }));
+                }
+            }
+        }
+
+        // Transforms keep the planes, like the other primitives
+        let transform = DAffine3::from_scale_rotation_translation(
+            DVec3::new(2.0, 1.0, 1.0),
+            DQuat::from_rotation_y(std::f64::consts::FRAC_PI_2),
+            DVec3::new(-1.0, 0.0, 4.0),
+        );
+        let transformed = geodesic.transform(transform);
+        assert!(transformed.is_plane_defined());
+        assert_eq!(transformed.polygons.len(), 80);
+        let center = transform.transform_point3(origin);
+        assert!(transformed
+            .aabb
+            .min
+            .abs_diff_eq(center - DVec3::new(2.0, 2.0, 4.0), 1e-9));
+        assert!(transformed
+            .aabb
+            .max
+            .abs_diff_eq(center + DVec3::new(2.0, 2.0, 4.0), 1e-9));
+        for polygon in &transformed.polygons {
+            assert!(polygon.surface.normal.dot(polygon.vertices[0].pos - center) > 0.0);
+        }
+
+        // Face edits work on the planes
+        let mut moved = uv.clone();
+        moved.move_face(0, -0.1).unwrap();
+        assert_eq!(moved.polygons.len(), 32);
+        assert!(uv.extrude_face(0, 0.5, test_settings()).is_ok());
+    }
+
+    #[test]
+    fn test_from_capsule() {
+        let brushlet = Brushlet::from_capsule(
+            Capsule {
+                origin: DVec3::ZERO,
+                radius: 1.0,
+                height: 4.0,
+                segments: 8,
+                rings: 2,
+                axis: DVec3::Y,
+                material_idx: 0,
+            },
+            test_settings(),
+        );
+
+        // Two bands per cap and one band for the cylinder
+        assert_eq!(brushlet.polygons.len(), 40);
+        assert!((brushlet.aabb.min.y + 2.0).abs() < 1e-9);
+        assert!((brushlet.aabb.max.y - 2.0).abs() < 1e-9);
+        let surfaces = brushlet.surfaces.as_ref().unwrap();
+        for vertex in brushlet.polygons.iter().flat_map(|p| p.vertices.iter()) {
+            assert!(surfaces.iter().all(|surface| {
+                surface.normal.dot(vertex.pos) <= surface.distance_from_origin + Surface::EPSILON
+            }));
+        }
+    }
+
+    #[test]
+    fn test_move_and_extrude_face() {
+        let mut brushlet = Brushlet::from_cuboid(
+            Cuboid {
+                origin: DVec3::ZERO,
+                width: 2.0,
+                height: 2.0,
+                depth: 2.0,
+                material_indices: CuboidMaterialIndices {
+                    front: 0,
+                    back: 1,
+                    left: 2,
+                    right: 3,
+                    top: 4,
+                    bottom: 5,
+                },
+            },
+            test_settings(),
+        );
+
+        let hit = RaycastResult {
+            distance: 4.0,
+            point: DVec3::new(0.5, 1.0, 0.0),
+            normal: DVec3::Y,
+        };
+        let top = brushlet.face_from_raycast(&hit).unwrap();
+        assert_eq!(brushlet.surfaces.as_ref().unwrap()[top].material_idx, 4);
+
+        brushlet.move_face(top, 1.0).unwrap();
+        assert!(brushlet
+            .aabb
+            .max
+            .abs_diff_eq(DVec3::new(1.0, 2.0, 1.0), 1e-9));
+        let mut materials: Vec<usize> = brushlet
+            .polygons
+            .iter()
+            .map(|polygon| polygon.surface.material_idx)
+            .collect();
+        materials.sort();
+        assert_eq!(materials, vec![0, 1, 2, 3, 4, 5]);
+
+        assert!(matches!(
+            brushlet.move_face(top, -5.0),
+            Err(BrushError::EmptySolid)
+        ));
+        assert!(brushlet
+            .aabb
+            .max
+            .abs_diff_eq(DVec3::new(1.0, 2.0, 1.0), 1e-9));
+        assert!(matches!(
+            brushlet.move_face(6, 1.0),
+            Err(BrushError::FaceAtIndexDoesNotExist(6))
+        ));
+
+        let extruded = brushlet.extrude_face(top, 0.5, test_settings()).unwrap();
+        assert!(extruded
+            .aabb
+            .min
+            .abs_diff_eq(DVec3::new(-1.0, 2.0, -1.0), 1e-9));
+        assert!(extruded
+            .aabb
+            .max
+            .abs_diff_eq(DVec3::new(1.0, 2.5, 1.0), 1e-9));
+        assert!(extruded
+            .polygons
+            .iter()
+            .all(|polygon| polygon.surface.material_idx == 4));
+
+        // A chamfer that no longer touches the solid doesn't stay behind as a face
+        let mut chamfered =
+            Knife::new(DVec3::new(1.0, 1.0, 0.0), 1.5, 7).perform(&test_cuboid(DVec3::ZERO, 2.0));
+        assert_eq!(chamfered.surfaces.as_ref().unwrap().len(), 7);
+        let top = chamfered
+            .surfaces
+            .as_ref()
+            .unwrap()
+            .iter()
+            .position(|surface| surface.normal.abs_diff_eq(DVec3::Y, 1e-9))
+            .unwrap();
+        chamfered.move_face(top, -0.75).unwrap();
+        assert_eq!(chamfered.surfaces.as_ref().unwrap().len(), 6);
+        assert_eq!(chamfered.polygons.len(), 6);
+        assert!(chamfered
+            .surfaces
+            .as_ref()
+            .unwrap()
+            .iter()
+            .all(|surface| surface.material_idx != 7));
+    }
+
+    #[test]
+    fn test_bevel() {
+        let cuboid = test_cuboid(DVec3::ZERO, 2.0);
+        let bevel = Bevel {
+            edges: BevelEdges::Vertical,
+            distance: 0.25,
+            segments: 1,
+            material_index: 1,
+        };
+
+        let chamfered = cuboid.bevel(&bevel).unwrap();
+        assert_eq!(chamfered.polygons.len(), 10);
+        assert!(chamfered.aabb.max.abs_diff_eq(DVec3::ONE, 1e-9));
+        for polygon in chamfered
+            .polygons
+            .iter()
+            .filter(|polygon| polygon.surface.material_idx == 1)
+        {
+            assert!(polygon.surface.normal.y.abs() < 1e-9);
+            for vertex in &polygon.vertices {
+                let corner = (vertex.pos.x.abs() - 1.0)
+                    .abs()
+                    .max((vertex.pos.z.abs() - 1.0).abs());
+                assert!((corner - 0.25).abs() < 1e-9);
+            }
+        }
+
+        // A rounded edge between the top and front faces
+        let surfaces = cuboid.surfaces.as_ref().unwrap();
+        let face = |normal: DVec3| {
+            surfaces
+                .iter()
+                .position(|surface| surface.normal.abs_diff_eq(normal, 1e-9))
+                .unwrap()
+        };
+        let rounded = cuboid
+            .bevel(&Bevel {
+                edges: BevelEdges::Faces(vec![(face(DVec3::Y), face(DVec3::Z))]),
+                segments: 4,
+                ..bevel.clone()
+            })
+            .unwrap();
+        assert_eq!(rounded.polygons.len(), 10);
+        let center = DVec3::new(0.0, 0.75, 0.75);
+        for polygon in rounded
+            .polygons
+            .iter()
+            .filter(|polygon| polygon.surface.material_idx == 1)
+        {
+            for vertex in &polygon.vertices {
+                let offset = vertex.pos - center;
+                assert!((offset.y.hypot(offset.z) - 0.25).abs() < 1e-9);
+            }
+        }
+
+        let all = cuboid
+            .bevel(&Bevel {
+                edges: BevelEdges::All,
+                ..bevel.clone()
+            })
+            .unwrap();
+        assert_eq!(all.surfaces.as_ref().unwrap().len(), 6 + 12);
+        assert!(all.aabb.max.abs_diff_eq(DVec3::ONE, 1e-9));
+
+        assert!(matches!(
+            cuboid.bevel(&Bevel {
+                edges: BevelEdges::Faces(vec![(0, 9)]),
+                ..bevel
+            }),
+            Err(BrushError::FaceAtIndexDoesNotExist(9))
+        ));
+    }
+
+    #[test]
+    fn test_hollow() {
+        let cuboid = Brushlet::from_cuboid(
+            Cuboid {
+                origin: DVec3::ZERO,
+                width: 4.0,
+                height: 4.0,
+                depth: 4.0,
+                material_indices: CuboidMaterialIndices {
+                    front: 0,
+                    back: 1,
+                    left: 2,
+                    right: 3,
+                    top: 4,
+                    bottom: 5,
+                },
+            },
+            test_settings(),
+        );
+        let contains = |brushlet: &Brushlet, point: DVec3| {
+            brushlet.surfaces.as_ref().unwrap().iter().all(|surface| {
+                surface.normal.dot(point) <= surface.distance_from_origin + Surface::EPSILON
+            })
+        };
+        let walls_containing = |brush: &Brush, point: DVec3| {
+            brush
+                .brushlets
+                .iter()
+                .filter(|brushlet| contains(brushlet, point))
+                .count()
+        };
+
+        for joint in [HollowJoint::Mitered, HollowJoint::Overlapping] {
+            let brush = cuboid
+                .hollow(&Hollow {
+                    thickness: 1.0,
+                    joint,
+                    interior_material_index: 9,
+                })
+                .unwrap();
+            assert_eq!(brush.brushlets.len(), 6);
+            assert_eq!(walls_containing(&brush, DVec3::ZERO), 0);
+            assert_eq!(walls_containing(&brush, DVec3::new(0.0, 1.5, 0.0)), 1);
+
+            let near_edge = walls_containing(&brush, DVec3::new(1.6, 1.5, 0.0));
+            match joint {
+                HollowJoint::Mitered => assert_eq!(near_edge, 1),
+                HollowJoint::Overlapping => assert_eq!(near_edge, 2),
+            }
+
+            let mesh_data = brush.to_mesh_data();
+            for polygon in &mesh_data.polygons {
+                let normal = polygon.surface.normal;
+                let on_outside = polygon
+                    .vertices
+                    .iter()
+                    .all(|vertex| (vertex.pos.dot(normal) - 2.0).abs() < 1e-6);
+                if on_outside {
+                    assert_ne!(polygon.surface.material_idx, 9);
+                } else {
+                    assert_eq!(polygon.surface.material_idx, 9);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_knife_keeps_surfaces() {
+        let mut brushlet = Brushlet::from_cuboid(
+            Cuboid {
+                origin: DVec3::ZERO,
+                width: 2.0,
+                height: 2.0,
+                depth: 2.0,
+                material_indices: CuboidMaterialIndices {
+                    front: 0,
+                    back: 1,
+                    left: 2,
+                    right: 3,
+                    top: 4,
+                    bottom: 5,
+                },
+            },
+            test_settings(),
+        );
+        let texture = TextureMapping {
+            u_axis: DVec3::X,
+            v_axis: -DVec3::Y,
+            offset: DVec2::new(0.25, 0.5),
+            scale: DVec2::new(2.0, 2.0),
+        };
+        let mut surfaces = brushlet.surfaces.clone().unwrap();
+        for surface in &mut surfaces {
+            if surface.material_idx == 0 {
+                *surface = surface.with_texture(texture);
+            }
+        }
+        brushlet.surfaces = Some(surfaces);
+        brushlet.regenerate_polygons();
+
+        // Slices the top right edge off at 45 degrees, unnormalized like in the README
+        let knife = Knife::new(DVec3::new(1.0, 1.0, 0.0), 1.0, 7);
+
+        let baked = Brushlet {
+            surfaces: None,
+            ..brushlet.clone()
+        };
+        for cut in [knife.perform(&brushlet), knife.perform(&baked)] {
+            let mut materials: Vec<usize> = cut
+                .polygons
+                .iter()
+                .map(|polygon| polygon.surface.material_idx)
+                .collect();
+            materials.sort();
+            materials.dedup();
+            assert_eq!(materials, vec![0, 1, 2, 3, 4, 5, 7]);
+
+            for polygon in &cut.polygons {
+                match polygon.surface.material_idx {
+                    0 => assert_eq!(polygon.surface.texture, Some(texture)),
+                    7 => {
+                        let normal = DVec3::new(1.0, 1.0, 0.0).normalize();
+                        assert!(polygon.surface.normal.abs_diff_eq(normal, 1e-9));
+                        assert!(polygon.vertices.iter().all(|vertex| (vertex.pos.x
+                            + vertex.pos.y
+                            - 1.0)
+                            .abs()
+                            < 1e-9));
+                    }
+                    _ => assert_eq!(polygon.surface.texture, None),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_knife_transform() {
+        // Unnormalized, the plane is x + y + z = -4
+        let knife = Knife::new(DVec3::new(-1.0, -1.0, -1.0), 4.0, 7);
+        let cube = test_cuboid(DVec3::ZERO, 6.0);
+        let cut = knife.perform(&cube);
+        let offset = DVec3::new(3.0, -2.0, 5.0);
+
+        let identity = knife.transform(DAffine3::IDENTITY);
+        assert!((identity.distance_from_origin - 4.0 / 3.0f64.sqrt()).abs() < 1e-9);
+        let moved = knife.transform(DAffine3::from_translation(offset));
+        for (knife, offset) in [(identity, DVec3::ZERO), (moved, offset)] {
+            let moved_cut = knife.perform(&cube.transform(DAffine3::from_translation(offset)));
+            assert!(moved_cut.aabb.min.abs_diff_eq(cut.aabb.min + offset, 1e-9));
+            assert!(moved_cut.aabb.max.abs_diff_eq(cut.aabb.max + offset, 1e-9));
+            for polygon in moved_cut
+                .polygons
+                .iter()
+                .filter(|polygon| polygon.surface.material_idx == 7)
+            {
+                for vertex in &polygon.vertices {
+                    let pos = vertex.pos - offset;
+                    assert!((pos.x + pos.y + pos.z + 4.0).abs() < 1e-9);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_compound_and_bounded_knives() {
+        let cuboid = |origin: DVec3| test_cuboid(origin, 2.0);
+        let volume = |brushlet: &Brushlet| {
+            brushlet
+                .polygons
+                .iter()
+                .flat_map(|polygon| {
+                    let a = polygon.vertices[0].pos;
+                    polygon
+                        .vertices
+                        .windows(2)
+                        .skip(1)
+                        .map(move |pair| a.dot(pair[0].pos.cross(pair[1].pos)) / 6.0)
+                })
+                .sum::<f64>()
+        };
+        let materials = |brushlet: &Brushlet| {
+            let mut materials: Vec<usize> = brushlet
+                .polygons
+                .iter()
+                .map(|polygon| polygon.surface.material_idx)
+                .collect();
+            materials.sort();
+            materials.dedup();
+            materials
+        };
+
+        // A V-notch along Z in the top face, 1 wide and 0.5 deep
+        let notch = Knife::new(DVec3::new(-1.0, 1.0, 0.0), 0.5, 7).with_profile(Surface::new(
+            DVec3::new(1.0, 1.0, 0.0).normalize(),
+            0.5 / 2.0f64.sqrt(),
+            8,
+        ));
+        let notched = notch.perform(&cuboid(DVec3::ZERO));
+        assert!(notched.surfaces.is_none());
+        assert!((volume(&notched) - 7.5).abs() < 1e-9);
+        assert_eq!(materials(&notched), vec![0, 7, 8]);
+
+        // Only cuts the top half in front of x = 0
+        let partial = Knife::new(DVec3::Y, 0.0, 3)
+            .with_bounds_aabb(Aabb::new(DVec3::new(0.0, -10.0, -10.0), DVec3::splat(10.0)));
+        let partial = partial.perform(&cuboid(DVec3::ZERO));
+        assert!((volume(&partial) - 6.0).abs() < 1e-9);
+        assert!(partial
+            .polygons
+            .iter()
+            .filter(|polygon| polygon.surface.material_idx == 3)
+            .all(|polygon| {
+                polygon.vertices.iter().all(|vertex| {
+                    (vertex.pos.y.abs() < 1e-9 && vertex.pos.x >= -1e-9)
+                        || (vertex.pos.x.abs() < 1e-9 && vertex.pos.y >= -1e-9)
+                })
+            }));
+
+        // The same notch from unnormalized planes, stopping at z = 0.5, moves along with the knife
+        let offset = DVec3::new(-4.0, 3.0, 2.0);
+        let unnormalized = Knife::new(DVec3::new(-2.0, 2.0, 0.0), 1.0, 7)
+            .with_profile(Surface::new(DVec3::new(3.0, 3.0, 0.0), 1.5, 8))
+            .with_bounds(vec![Surface::new(DVec3::Z * 4.0, 2.0, 9)]);
+        let moved = unnormalized
+            .transform(DAffine3::from_translation(offset))
+            .perform(&cuboid(offset));
+        assert!((volume(&moved) - 8.0 + 0.375).abs() < 1e-9);
+        assert_eq!(materials(&moved), vec![0, 7, 8, 9]);
+        assert!(moved.aabb.max.abs_diff_eq(offset + DVec3::ONE, 1e-9));
+
+        // Cuts far away from the origin still work
+        let far = DVec3::new(2e5, 0.0, 0.0);
+        let knife = Knife::new(DVec3::X, far.x, 3);
+        for brushlet in [
+            cuboid(far),
+            Brushlet {
+                surfaces: None,
+                ..cuboid(far)
+            },
+        ] {
+            let cut = knife.perform(&brushlet);
+            assert!(cut.aabb.max.abs_diff_eq(DVec3::new(far.x, 1.0, 1.0), 1e-6));
+            assert!(cut.aabb.min.abs_diff_eq(far - DVec3::ONE, 1e-6));
+        }
+    }
+
+    #[test]
+    fn test_csg_merges_coplanar_polygons() {
+        let cuboid = |origin: DVec3, width: f64, depth: f64, top: usize| {
+            Brushlet::from_cuboid(
+                Cuboid {
+                    origin,
+                    width,
+                    height: 1.0,
+                    depth,
+                    material_indices: CuboidMaterialIndices {
+                        top,
+                        ..Default::default()
+                    },
+                },
+                test_settings(),
+            )
+        };
+        let is_convex = |polygon: &Polygon| {
+            let len = polygon.vertices.len();
+            (0..len).all(|idx| {
+                let prev = polygon.vertices[(idx + len - 1) % len].pos;
+                let pos = polygon.vertices[idx].pos;
+                let next = polygon.vertices[(idx + 1) % len].pos;
+                (pos - prev).cross(next - pos).dot(polygon.surface.normal) > -1e-9
+            })
+        };
+
+        // Two boxes side by side union into a single box
+        let left = cuboid(DVec3::ZERO, 1.0, 1.0, 0);
+        let merged = left.union(&cuboid(DVec3::X, 1.0, 1.0, 0));
+        assert_eq!(merged.polygons.len(), 6);
+        assert!(merged
+            .polygons
+            .iter()
+            .all(|polygon| polygon.vertices.len() == 4));
+
+        // Different materials stay apart
+        let two_tone = left.union(&cuboid(DVec3::X, 1.0, 1.0, 1));
+        assert_eq!(two_tone.polygons.len(), 7);
+
+        // Carving a notch out of a slab leaves a concave top made of convex pieces
+        let slab = cuboid(DVec3::ZERO, 4.0, 4.0, 0);
+        let notched = slab.subtract(&cuboid(DVec3::new(1.0, 0.0, 1.0), 2.0, 2.0, 0));
+        assert!(notched.polygons.iter().all(is_convex));
+        let top: Vec<&Polygon> = notched
+            .polygons
+            .iter()
+            .filter(|polygon| polygon.surface.normal.abs_diff_eq(DVec3::Y, 1e-9))
+            .collect();
+        assert_eq!(top.len(), 2);
+    }
+
+    #[test]
+    fn test_validating_constructors() {
+        let cube: Vec<Surface> = [
+            DVec3::X,
+            -DVec3::X,
+            DVec3::Y,
+            -DVec3::Y,
+            DVec3::Z,
+            -DVec3::Z,
+        ]
+        .into_iter()
+        .map(|normal| Surface::new(normal, 1.0, 0))
+        .collect();
+        let try_with = |surfaces: Vec<Surface>| {
+            Brushlet::try_from_surfaces(surfaces, test_settings())
+                .map(|brushlet| brushlet.polygons.len())
+        };
+
+        assert_eq!(try_with(cube.clone()), Ok(6));
+        assert!(Brushlet::from_surfaces(cube.clone(), test_settings())
+            .validate()
+            .is_ok());
+
+        let mut duplicate = cube.clone();
+        duplicate.push(Surface::new(DVec3::Y * 2.0, 2.0, 1));
+        assert_eq!(try_with(duplicate), Err(BrushError::DuplicatePlane(2, 6)));
+
+        let mut redundant = cube.clone();
+        redundant.push(Surface::new(DVec3::ONE.normalize(), 10.0, 0));
+        assert_eq!(try_with(redundant), Err(BrushError::RedundantPlane(6)));
+
+        let open: Vec<Surface> = cube
+            .iter()
+            .copied()
+            .filter(|s| s.normal != DVec3::Y)
+            .collect();
+        assert_eq!(try_with(open), Err(BrushError::UnboundedSolid));
+        assert_eq!(
+            try_with(cube[..2].to_vec()),
+            Err(BrushError::UnboundedSolid)
+        );
+
+        let mut empty = cube.clone();
+        empty[0].distance_from_origin = -2.0;
+        assert_eq!(try_with(empty), Err(BrushError::EmptySolid));
+
+        let mut nan = cube.clone();
+        nan[3].distance_from_origin = f64::NAN;
+        assert_eq!(try_with(nan), Err(BrushError::NonFiniteInput));
+        assert_eq!(
+            BrushError::DuplicatePlane(2, 6).to_string(),
+            "planes 2 and 6 are the same"
+        );
+
+        // Polygons and planes from points
+        let vertex = |x: f64, y: f64| Vertex::new(DVec3::new(x, y, 0.0), DVec3::Z);
+        assert!(matches!(
+            Surface::try_from_points(DVec3::ZERO, DVec3::X, DVec3::X * 2.0, 0),
+            Err(BrushError::DegeneratePolygon)
+        ));
+        assert_eq!(
+            Polygon::try_new(vec![vertex(0.0, 0.0), vertex(1.0, 0.0)], 0).err(),
+            Some(BrushError::DegeneratePolygon)
+        );
+        assert_eq!(
+            Polygon::try_new(
+                vec![vertex(0.0, 0.0), vertex(1.0, 0.0), vertex(f64::NAN, 1.0)],
+                0
+            )
+            .err(),
+            Some(BrushError::NonFiniteInput)
+        );
+        let arrow = vec![
+            vertex(0.0, 0.0),
+            vertex(2.0, 0.0),
+            vertex(2.0, 2.0),
+            vertex(1.0, 0.5),
+            vertex(0.0, 2.0),
+        ];
+        assert_eq!(
+            Polygon::try_new(arrow, 0).err(),
+            Some(BrushError::NonConvex)
+        );
+
+        // The first three points are collinear, which `Polygon::new` can't handle
+        let polygon = Polygon::try_new(
+            vec![
+                vertex(0.0, 0.0),
+                vertex(1.0, 0.0),
+                vertex(2.0, 0.0),
+                vertex(2.0, 1.0),
+                vertex(0.0, 1.0),
+            ],
+            3,
+        )
+        .unwrap();
+        assert!(polygon.surface.normal.abs_diff_eq(DVec3::Z, 1e-12));
+        assert_eq!(polygon.surface.material_idx, 3);
     }
 }
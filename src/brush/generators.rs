@@ -0,0 +1,793 @@
+//! Generators that build whole brushes out of several brushlets.
+
+#[cfg(feature = "bevy")]
+use bevy::math::{DVec2, DVec3};
+
+#[cfg(not(feature = "bevy"))]
+use glam::{DVec2, DVec3};
+
+use super::{
+    brushlet::{Brushlet, BrushletSettings},
+    BooleanOp, Brush, BrushError, BrushSettings,
+};
+use crate::{
+    primitives::{
+        Arch, ArchShape, Cuboid, CuboidMaterialIndices, Extrusion, Pipe, StairShape, Stairs,
+    },
+    surface::Surface,
+    util::{clean_outline_2d, convex_decomposition_2d, is_convex_2d, prism_surfaces},
+};
+
+/// The largest angle a single curved brushlet may cover, so it stays convex.
+const MAX_SEGMENT_SWEEP: f64 = std::f64::consts::FRAC_PI_2;
+
+impl Brush {
+    /// Creates a brush from stairs, with one unioned brushlet per step and one for the landing.
+    ///
+    /// Spiral steps and landings covering more than a quarter turn are split
+    /// into several brushlets so each of them stays convex.
+    pub fn from_stairs(stairs: Stairs, settings: BrushSettings) -> Self {
+        let steps = stairs.steps.max(1);
+        let step_height = stairs.rise / steps as f64;
+        let step_run = stairs.run / steps as f64;
+        let landing = stairs.landing.filter(|landing| *landing > 0.0);
+
+        let mut brushlets = Vec::with_capacity(steps + 1);
+        match stairs.shape {
+            StairShape::Straight => {
+                let materials = &stairs.material_indices;
+                let block = |start: f64, length: f64, height: f64, front: usize| Cuboid {
+                    origin: stairs.origin + DVec3::new(0.0, height * 0.5, -start - length * 0.5),
+                    width: stairs.width,
+                    height,
+                    depth: length,
+                    material_indices: CuboidMaterialIndices {
+                        top: materials.tread,
+                        bottom: materials.stringer,
+                        front,
+                        back: materials.stringer,
+                        left: materials.stringer,
+                        right: materials.stringer,
+                    },
+                };
+
+                for i in 0..steps {
+                    brushlets.push(Brushlet::from_cuboid(
+                        block(
+                            i as f64 * step_run,
+                            step_run,
+                            (i + 1) as f64 * step_height,
+                            materials.riser,
+                        ),
+                        union_settings(format!("Step {}", i + 1)),
+                    ));
+                }
+                if let Some(landing) = landing {
+                    brushlets.push(Brushlet::from_cuboid(
+                        block(stairs.run, landing, stairs.rise, materials.stringer),
+                        union_settings("Landing".to_string()),
+                    ));
+                }
+            }
+            StairShape::Spiral { inner_radius } => {
+                let inner_radius = inner_radius.max(0.0);
+                let middle_radius = inner_radius + stairs.width * 0.5;
+                let step_sweep = step_run / middle_radius;
+                // Blocks covering `sweep` from `start`, split into convex pieces
+                let blocks = |start: f64, sweep: f64, height: f64, front: usize| {
+                    let pieces = (sweep / MAX_SEGMENT_SWEEP).ceil().max(1.0) as usize;
+                    let piece_sweep = sweep / pieces as f64;
+                    (0..pieces)
+                        .map(|i| {
+                            // Only the first piece faces the previous step
+                            let front = if i == 0 {
+                                front
+                            } else {
+                                stairs.material_indices.stringer
+                            };
+                            spiral_block(
+                                &stairs,
+                                inner_radius,
+                                start + i as f64 * piece_sweep,
+                                piece_sweep,
+                                height,
+                                front,
+                            )
+                        })
+                        .collect::<Vec<_>>()
+                };
+
+                for i in 0..steps {
+                    let pieces = blocks(
+                        i as f64 * step_sweep,
+                        step_sweep,
+                        (i + 1) as f64 * step_height,
+                        stairs.material_indices.riser,
+                    );
+                    let split = pieces.len() > 1;
+                    for (k, surfaces) in pieces.into_iter().enumerate() {
+                        let name = if split {
+                            format!("Step {} Part {}", i + 1, k + 1)
+                        } else {
+                            format!("Step {}", i + 1)
+                        };
+                        brushlets.push(Brushlet::from_surfaces(surfaces, union_settings(name)));
+                    }
+                }
+                if let Some(landing) = landing {
+                    let pieces = blocks(
+                        steps as f64 * step_sweep,
+                        landing / middle_radius,
+                        stairs.rise,
+                        stairs.material_indices.stringer,
+                    );
+                    for (i, surfaces) in pieces.into_iter().enumerate() {
+                        brushlets.push(Brushlet::from_surfaces(
+                            surfaces,
+                            union_settings(format!("Landing {}", i + 1)),
+                        ));
+                    }
+                }
+            }
+        }
+
+        Self {
+            brushlets,
+            settings,
+        }
+    }
+
+    /// Creates a brush from an arch.
+    ///
+    /// An arch with a thickness is made of one unioned brushlet per segment of
+    /// the curve plus one per leg, otherwise it is a single brushlet filling the opening.
+    pub fn from_arch(arch: Arch, settings: BrushSettings) -> Self {
+        let materials = &arch.material_indices;
+        let half_span = arch.span * 0.5;
+        let leg_height = arch.leg_height.max(0.0);
+        let has_legs = leg_height > Surface::EPSILON;
+        let extrusion = DVec3::Z * arch.depth;
+        let to_world = |point: DVec2| arch.origin + DVec3::new(point.x, point.y, -arch.depth * 0.5);
+        let prism = |outline: Vec<DVec2>, side_materials: &[usize], name: String| {
+            let outline: Vec<DVec3> = outline.into_iter().map(to_world).collect();
+            Brushlet::from_surfaces(
+                prism_surfaces(
+                    &outline,
+                    extrusion,
+                    side_materials,
+                    (materials.faces, materials.faces),
+                ),
+                union_settings(name),
+            )
+        };
+        let curve = |offset: f64| -> Vec<DVec2> {
+            arch_curve(arch.shape, arch.span, offset, arch.segments)
+                .into_iter()
+                .map(|point| point + DVec2::Y * leg_height)
+                .collect()
+        };
+
+        let mut brushlets = Vec::new();
+        match arch.thickness.filter(|thickness| *thickness > 0.0) {
+            None => {
+                let mut outline = Vec::new();
+                if has_legs {
+                    outline.push(DVec2::new(half_span, 0.0));
+                }
+                outline.extend(curve(0.0));
+                if has_legs {
+                    outline.push(DVec2::new(-half_span, 0.0));
+                }
+                let side_materials = vec![materials.inner; outline.len()];
+                brushlets.push(prism(outline, &side_materials, "Opening".to_string()));
+            }
+            Some(thickness) => {
+                let inner = curve(0.0);
+                let outer = curve(thickness);
+                for k in 0..inner.len() - 1 {
+                    brushlets.push(prism(
+                        vec![inner[k], inner[k + 1], outer[k + 1], outer[k]],
+                        &[
+                            materials.inner,
+                            materials.outer,
+                            materials.outer,
+                            materials.outer,
+                        ],
+                        format!("Segment {}", k + 1),
+                    ));
+                }
+                if has_legs {
+                    let outer_edge = half_span + thickness;
+                    brushlets.push(prism(
+                        vec![
+                            DVec2::new(half_span, 0.0),
+                            DVec2::new(outer_edge, 0.0),
+                            DVec2::new(outer_edge, leg_height),
+                            DVec2::new(half_span, leg_height),
+                        ],
+                        &[
+                            materials.outer,
+                            materials.outer,
+                            materials.outer,
+                            materials.inner,
+                        ],
+                        "Right Leg".to_string(),
+                    ));
+                    brushlets.push(prism(
+                        vec![
+                            DVec2::new(-outer_edge, 0.0),
+                            DVec2::new(-half_span, 0.0),
+                            DVec2::new(-half_span, leg_height),
+                            DVec2::new(-outer_edge, leg_height),
+                        ],
+                        &[
+                            materials.outer,
+                            materials.inner,
+                            materials.outer,
+                            materials.outer,
+                        ],
+                        "Left Leg".to_string(),
+                    ));
+                }
+            }
+        }
+
+        Self {
+            brushlets,
+            settings,
+        }
+    }
+
+    /// Creates a brush from a bent pipe, with one brushlet per segment of the bend.
+    ///
+    /// Hollow pipes subtract a thinner pipe from the unioned segments.
+    pub fn from_pipe(pipe: Pipe, settings: BrushSettings) -> Self {
+        let materials = &pipe.material_indices;
+        let sweep = pipe.sweep.clamp(0.0, std::f64::consts::TAU);
+        let segments = pipe
+            .segments
+            .max((sweep / MAX_SEGMENT_SWEEP).ceil() as usize)
+            .max(1);
+        let sides = pipe.sides.max(3);
+        let direction = |angle: f64| DVec3::new(angle.cos(), 0.0, -angle.sin());
+
+        let segment = |k: usize, radius: f64, side_material: usize, operation: BooleanOp| {
+            let mut points = Vec::with_capacity(sides * 2);
+            for angle in [k, k + 1].map(|k| sweep * k as f64 / segments as f64) {
+                let center = pipe.origin + direction(angle) * pipe.bend_radius;
+                for j in 0..sides {
+                    let around = std::f64::consts::TAU * j as f64 / sides as f64;
+                    points.push(
+                        center
+                            + (direction(angle) * around.cos() + DVec3::Y * around.sin()) * radius,
+                    );
+                }
+            }
+
+            let mut faces = vec![
+                ((0..sides).collect(), materials.ends),
+                ((sides..sides * 2).collect(), materials.ends),
+            ];
+            for j in 0..sides {
+                let next = (j + 1) % sides;
+                faces.push((vec![j, next, sides + next, sides + j], side_material));
+            }
+
+            let center = points.iter().sum::<DVec3>() / points.len() as f64;
+            Brushlet::from_faces(
+                &points,
+                faces,
+                center,
+                BrushletSettings {
+                    name: format!("Segment {}", k + 1),
+                    operation,
+                    knives: Vec::new(),
+                    inverted: false,
+                },
+            )
+        };
+
+        let mut brushlets: Vec<Brushlet> = (0..segments)
+            .map(|k| segment(k, pipe.radius, materials.outer, BooleanOp::Union))
+            .collect();
+        if let Some(wall_thickness) = pipe.wall_thickness {
+            let inner_radius = pipe.radius - wall_thickness;
+            if inner_radius > Surface::EPSILON {
+                brushlets.extend(
+                    (0..segments)
+                        .map(|k| segment(k, inner_radius, materials.inner, BooleanOp::Subtract)),
+                );
+            }
+        }
+
+        Self {
+            brushlets,
+            settings,
+        }
+    }
+
+    /// Creates a brush from an extrusion.
+    ///
+    /// Convex outlines give a single brushlet, concave ones are split into
+    /// convex pieces that are unioned together.
+    pub fn from_extrusion(
+        extrusion: Extrusion,
+        settings: BrushSettings,
+    ) -> Result<Self, BrushError> {
+        let tolerance = &settings.tolerance;
+        let outline = clean_outline_2d(&extrusion.outline, tolerance);
+        if outline.len() < 3 || extrusion.extrusion.length_squared() == 0.0 {
+            return Err(BrushError::DegeneratePolygon);
+        }
+
+        let brushlets = if is_convex_2d(&outline, tolerance) {
+            vec![Brushlet::from_convex_extrusion(
+                &extrusion,
+                &outline,
+                union_settings("Extrusion".to_string()),
+                tolerance,
+            )]
+        } else {
+            convex_decomposition_2d(&outline, tolerance)
+                .into_iter()
+                .enumerate()
+                .map(|(i, piece)| {
+                    Brushlet::from_convex_extrusion(
+                        &extrusion,
+                        &piece,
+                        union_settings(format!("Piece {}", i + 1)),
+                        tolerance,
+                    )
+                })
+                .collect()
+        };
+
+        Ok(Self {
+            brushlets,
+            settings,
+        })
+    }
+}
+
+fn union_settings(name: String) -> BrushletSettings {
+    BrushletSettings {
+        name,
+        operation: BooleanOp::Union,
+        knives: Vec::new(),
+        inverted: false,
+    }
+}
+
+/// The points along the inside of an arch, offset outwards by `offset`,
+/// from the right springing point to the left one.
+fn arch_curve(shape: ArchShape, span: f64, offset: f64, segments: usize) -> Vec<DVec2> {
+    let half_span = span * 0.5;
+    let segments = segments.max(1);
+    let arc = |radii: DVec2, segments: usize| -> Vec<DVec2> {
+        (0..=segments)
+            .map(|k| {
+                let angle = std::f64::consts::PI * k as f64 / segments as f64;
+                DVec2::new(angle.cos(), angle.sin()) * radii
+            })
+            .collect()
+    };
+
+    match shape {
+        ArchShape::Semicircular => arc(DVec2::splat(half_span + offset), segments),
+        ArchShape::Elliptical { rise } => {
+            arc(DVec2::new(half_span + offset, rise + offset), segments)
+        }
+        ArchShape::Pointed => {
+            // Each side is an arc centered on the opposite springing point
+            let radius = span + offset;
+            let end = (half_span / radius).acos();
+            let half_segments = segments.div_ceil(2);
+            let mut points = Vec::with_capacity(half_segments * 2 + 1);
+            for k in 0..=half_segments {
+                let angle = end * k as f64 / half_segments as f64;
+                points.push(
+                    DVec2::new(-half_span, 0.0) + DVec2::new(angle.cos(), angle.sin()) * radius,
+                );
+            }
+            for k in 1..=half_segments {
+                let angle = std::f64::consts::PI - end + end * k as f64 / half_segments as f64;
+                points.push(
+                    DVec2::new(half_span, 0.0) + DVec2::new(angle.cos(), angle.sin()) * radius,
+                );
+            }
+            points
+        }
+    }
+}
+
+/// The planes of a block of spiral stairs from the floor up to `height`,
+/// covering `sweep` radians from `start`.
+fn spiral_block(
+    stairs: &Stairs,
+    inner_radius: f64,
+    start: f64,
+    sweep: f64,
+    height: f64,
+    front_material: usize,
+) -> Vec<Surface> {
+    let origin = stairs.origin;
+    let materials = &stairs.material_indices;
+    let outer_radius = inner_radius + stairs.width;
+    // Counterclockwise seen from above, starting towards +X
+    let direction = |angle: f64| DVec3::new(angle.cos(), 0.0, -angle.sin());
+    let tangent = |angle: f64| DVec3::new(-angle.sin(), 0.0, -angle.cos());
+    let middle = direction(start + sweep * 0.5);
+
+    let mut surfaces = vec![
+        Surface::new(DVec3::Y, origin.y + height, materials.tread),
+        Surface::new(-DVec3::Y, -origin.y, materials.stringer),
+        Surface::new(-tangent(start), -tangent(start).dot(origin), front_material),
+        Surface::new(
+            tangent(start + sweep),
+            tangent(start + sweep).dot(origin),
+            materials.stringer,
+        ),
+        // The outer corners lie on the outer radius
+        Surface::new(
+            middle,
+            middle.dot(origin) + outer_radius * (sweep * 0.5).cos(),
+            materials.stringer,
+        ),
+    ];
+    if inner_radius > 0.0 {
+        surfaces.push(Surface::new(
+            -middle,
+            -middle.dot(origin) - inner_radius,
+            materials.stringer,
+        ));
+    }
+    surfaces
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        brush::brushlet::tests::test_settings,
+        primitives::{
+            ArchMaterialIndices, ExtrusionMaterialIndices, PipeMaterialIndices,
+            StairMaterialIndices,
+        },
+    };
+
+    fn stairs(shape: StairShape, landing: Option<f64>) -> Stairs {
+        Stairs {
+            origin: DVec3::ZERO,
+            width: 2.0,
+            rise: 2.0,
+            run: 4.0,
+            steps: 4,
+            landing,
+            shape,
+            material_indices: StairMaterialIndices {
+                tread: 0,
+                riser: 1,
+                stringer: 2,
+            },
+        }
+    }
+
+    #[test]
+    fn test_straight_stairs() {
+        let brush = Brush::from_stairs(
+            stairs(StairShape::Straight, Some(1.0)),
+            Brush::new("Test").settings,
+        );
+        assert_eq!(brush.brushlets.len(), 5);
+        assert!(brush
+            .brushlets
+            .iter()
+            .all(|brushlet| brushlet.settings.operation == BooleanOp::Union));
+
+        let mesh_data = brush.to_mesh_data();
+        let mut tread_heights: Vec<f64> = mesh_data
+            .polygons
+            .iter()
+            .filter(|polygon| polygon.surface.material_idx == 0)
+            .map(|polygon| polygon.vertices[0].pos.y)
+            .collect();
+        tread_heights.sort_by(f64::total_cmp);
+        tread_heights.dedup_by(|a, b| (*a - *b).abs() < 1e-9);
+        assert_eq!(tread_heights, vec![0.5, 1.0, 1.5, 2.0]);
+
+        for polygon in &mesh_data.polygons {
+            for vertex in &polygon.vertices {
+                assert!(vertex.pos.z <= 1e-9 && vertex.pos.z >= -5.0 - 1e-9);
+                assert!(vertex.pos.x.abs() <= 1.0 + 1e-9);
+            }
+            if polygon.surface.material_idx == 1 {
+                assert!(polygon.surface.normal.abs_diff_eq(DVec3::Z, 1e-9));
+            }
+        }
+    }
+
+    #[test]
+    fn test_spiral_stairs() {
+        let brush = Brush::from_stairs(
+            stairs(StairShape::Spiral { inner_radius: 1.0 }, Some(4.0)),
+            Brush::new("Test").settings,
+        );
+        // The landing covers two radians, so it is split into two pieces
+        assert_eq!(brush.brushlets.len(), 6);
+
+        for brushlet in &brush.brushlets {
+            assert!(!brushlet.polygons.is_empty());
+            for vertex in brushlet.polygons.iter().flat_map(|p| p.vertices.iter()) {
+                let radius = vertex.pos.x.hypot(vertex.pos.z);
+                assert!((1.0 - 1e-9..=3.0 + 1e-9).contains(&radius));
+                assert!((-1e-9..=2.0 + 1e-9).contains(&vertex.pos.y));
+            }
+        }
+
+        let first_riser = brush.brushlets[0]
+            .polygons
+            .iter()
+            .find(|polygon| polygon.surface.material_idx == 1)
+            .unwrap();
+        assert!(first_riser.surface.normal.abs_diff_eq(DVec3::Z, 1e-9));
+
+        // Two steps going all the way around, half a turn each is split in two
+        let brush = Brush::from_stairs(
+            Stairs {
+                steps: 2,
+                run: std::f64::consts::TAU * 2.0,
+                ..stairs(StairShape::Spiral { inner_radius: 1.0 }, None)
+            },
+            Brush::new("Test").settings,
+        );
+        assert_eq!(brush.brushlets.len(), 4);
+        assert_eq!(brush.brushlets[1].settings.name, "Step 1 Part 2");
+        for brushlet in &brush.brushlets {
+            assert_eq!(brushlet.polygons.len(), 6);
+            for vertex in brushlet.polygons.iter().flat_map(|p| p.vertices.iter()) {
+                let radius = vertex.pos.x.hypot(vertex.pos.z);
+                assert!((1.0 - 1e-9..=3.0 + 1e-9).contains(&radius));
+            }
+        }
+        // The second step ends where the first one starts, covering its riser
+        let risers = brush
+            .to_mesh_data()
+            .polygons
+            .iter()
+            .filter(|polygon| polygon.surface.material_idx == 1)
+            .count();
+        assert_eq!(risers, 1);
+    }
+
+    fn arch(shape: ArchShape, thickness: Option<f64>) -> Arch {
+        Arch {
+            origin: DVec3::ZERO,
+            span: 2.0,
+            leg_height: 2.0,
+            depth: 0.5,
+            thickness,
+            segments: 8,
+            shape,
+            material_indices: ArchMaterialIndices {
+                faces: 0,
+                inner: 1,
+                outer: 2,
+            },
+        }
+    }
+
+    #[test]
+    fn test_arch_opening() {
+        let brush = Brush::from_arch(
+            arch(ArchShape::Semicircular, None),
+            Brush::new("Test").settings,
+        );
+        assert_eq!(brush.brushlets.len(), 1);
+        let opening = &brush.brushlets[0];
+        assert!(opening
+            .aabb
+            .min
+            .abs_diff_eq(DVec3::new(-1.0, 0.0, -0.25), 1e-9));
+        assert!(opening
+            .aabb
+            .max
+            .abs_diff_eq(DVec3::new(1.0, 3.0, 0.25), 1e-9));
+        // Front, back, the floor, two legs and the curve
+        assert_eq!(opening.polygons.len(), 2 + 1 + 2 + 8);
+
+        let pointed = Brush::from_arch(arch(ArchShape::Pointed, None), Brush::new("Test").settings);
+        assert!((pointed.brushlets[0].aabb.max.y - (2.0 + 3f64.sqrt())).abs() < 1e-9);
+
+        let elliptical = Brush::from_arch(
+            arch(ArchShape::Elliptical { rise: 0.5 }, None),
+            Brush::new("Test").settings,
+        );
+        assert!((elliptical.brushlets[0].aabb.max.y - 2.5).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_arch_ring() {
+        for shape in [
+            ArchShape::Semicircular,
+            ArchShape::Pointed,
+            ArchShape::Elliptical { rise: 0.5 },
+        ] {
+            let brush = Brush::from_arch(arch(shape, Some(0.5)), Brush::new("Test").settings);
+            assert_eq!(brush.brushlets.len(), 8 + 2);
+            for brushlet in &brush.brushlets {
+                assert_eq!(brushlet.polygons.len(), 6);
+            }
+
+            let mesh_data = brush.to_mesh_data();
+            let min_x = mesh_data
+                .polygons
+                .iter()
+                .flat_map(|polygon| polygon.vertices.iter())
+                .map(|vertex| vertex.pos.x)
+                .fold(f64::INFINITY, f64::min);
+            assert!((min_x + 1.5).abs() < 1e-9);
+            // Nothing is left inside the opening
+            for polygon in &mesh_data.polygons {
+                for vertex in &polygon.vertices {
+                    assert!(vertex.pos.x.abs() >= 1.0 - 1e-9 || vertex.pos.y >= 2.0 - 1e-9);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_pipe() {
+        let pipe = Pipe {
+            origin: DVec3::ZERO,
+            bend_radius: 4.0,
+            radius: 1.0,
+            wall_thickness: None,
+            sweep: std::f64::consts::PI,
+            segments: 3,
+            sides: 6,
+            material_indices: PipeMaterialIndices {
+                outer: 0,
+                inner: 1,
+                ends: 2,
+            },
+        };
+
+        let solid = Brush::from_pipe(pipe.clone(), Brush::new("Test").settings);
+        assert_eq!(solid.brushlets.len(), 3);
+        for brushlet in &solid.brushlets {
+            assert_eq!(brushlet.polygons.len(), 8);
+            for vertex in brushlet.polygons.iter().flat_map(|p| p.vertices.iter()) {
+                let ring = DVec3::new(vertex.pos.x, 0.0, vertex.pos.z).normalize() * 4.0;
+                assert!(((vertex.pos - ring).length() - 1.0).abs() < 1e-9);
+            }
+        }
+
+        let hollow = Brush::from_pipe(
+            Pipe {
+                wall_thickness: Some(0.25),
+                ..pipe
+            },
+            Brush::new("Test").settings,
+        );
+        assert_eq!(hollow.brushlets.len(), 6);
+        assert!(hollow.brushlets[3..]
+            .iter()
+            .all(|brushlet| brushlet.settings.operation == BooleanOp::Subtract));
+
+        let mesh_data = hollow.to_mesh_data();
+        assert!(mesh_data
+            .polygons
+            .iter()
+            .any(|polygon| polygon.surface.material_idx == 1));
+        // The ends are rings around the hollow inside
+        for polygon in mesh_data
+            .polygons
+            .iter()
+            .filter(|polygon| polygon.surface.material_idx == 2)
+        {
+            for vertex in &polygon.vertices {
+                let ring = DVec3::new(vertex.pos.x, 0.0, vertex.pos.z).normalize() * 4.0;
+                assert!((vertex.pos - ring).length() >= 0.75 - 1e-6);
+            }
+        }
+    }
+
+    fn extrusion(outline: Vec<DVec2>) -> Extrusion {
+        Extrusion::from_floor_plan(
+            outline,
+            3.0,
+            ExtrusionMaterialIndices {
+                sides: 0,
+                start: 1,
+                end: 2,
+            },
+        )
+    }
+
+    #[test]
+    fn test_convex_extrusion() {
+        let square = vec![
+            DVec2::new(0.0, 0.0),
+            DVec2::new(0.0, 2.0),
+            DVec2::new(1.0, 2.0),
+            DVec2::new(2.0, 2.0),
+            DVec2::new(2.0, 0.0),
+        ];
+        let brush =
+            Brush::from_extrusion(extrusion(square.clone()), Brush::new("Test").settings).unwrap();
+        assert_eq!(brush.brushlets.len(), 1);
+        let brushlet = &brush.brushlets[0];
+        assert_eq!(brushlet.polygons.len(), 6);
+        assert!(brushlet
+            .aabb
+            .min
+            .abs_diff_eq(DVec3::new(0.0, 0.0, -2.0), 1e-9));
+        assert!(brushlet
+            .aabb
+            .max
+            .abs_diff_eq(DVec3::new(2.0, 3.0, 0.0), 1e-9));
+        let top = brushlet
+            .polygons
+            .iter()
+            .find(|polygon| polygon.surface.material_idx == 2)
+            .unwrap();
+        assert!(top.surface.normal.abs_diff_eq(DVec3::Y, 1e-9));
+
+        assert!(Brushlet::from_extrusion(extrusion(square), test_settings()).is_ok());
+        assert_eq!(
+            Brush::from_extrusion(
+                extrusion(vec![DVec2::ZERO, DVec2::X, DVec2::X * 2.0]),
+                Brush::new("Test").settings
+            )
+            .err(),
+            Some(BrushError::DegeneratePolygon)
+        );
+    }
+
+    #[test]
+    fn test_concave_extrusion() {
+        // A U shaped floor plan, 3 wide and 2 deep with a notch in the middle
+        let outline = vec![
+            DVec2::new(0.0, 0.0),
+            DVec2::new(3.0, 0.0),
+            DVec2::new(3.0, 2.0),
+            DVec2::new(2.0, 2.0),
+            DVec2::new(2.0, 1.0),
+            DVec2::new(1.0, 1.0),
+            DVec2::new(1.0, 2.0),
+            DVec2::new(0.0, 2.0),
+        ];
+        assert_eq!(
+            Brushlet::from_extrusion(extrusion(outline.clone()), test_settings()).err(),
+            Some(BrushError::NonConvex)
+        );
+
+        let brush = Brush::from_extrusion(extrusion(outline), Brush::new("Test").settings).unwrap();
+        assert!(brush.brushlets.len() >= 3);
+
+        // The pieces tile the outline without overlapping
+        let mut area = 0.0;
+        for brushlet in &brush.brushlets {
+            let bottom = brushlet
+                .polygons
+                .iter()
+                .find(|polygon| polygon.surface.material_idx == 1)
+                .unwrap();
+            let points: Vec<DVec2> = bottom
+                .vertices
+                .iter()
+                .map(|vertex| DVec2::new(vertex.pos.x, -vertex.pos.z))
+                .collect();
+            area += crate::util::signed_area_2d(&points).abs() * 0.5;
+        }
+        assert!((area - 5.0).abs() < 1e-9);
+
+        let mesh_data = brush.to_mesh_data();
+        for polygon in &mesh_data.polygons {
+            for vertex in &polygon.vertices {
+                let (x, z) = (vertex.pos.x, -vertex.pos.z);
+                assert!(!(x > 1.0 + 1e-9 && x < 2.0 - 1e-9 && z > 1.0 + 1e-9));
+            }
+        }
+    }
+}
@@ -1,10 +1,15 @@
 pub mod brushlet;
+mod generators;
 mod node;
 pub mod operations;
 
+use std::{collections::HashMap, fmt};
+
 use crate::{
     broadphase::{Raycast, RaycastResult},
+    mesh::BatchedMesh,
     polygon::Polygon,
+    tolerance::Tolerance,
 };
 
 use brushlet::Brushlet;
@@ -12,7 +17,7 @@ use operations::Knife;
 
 #[cfg(feature = "bevy")]
 use bevy::{
-    math::DAffine3,
+    math::{DAffine3, DVec3},
     render::{
         mesh::{Indices, Mesh, PrimitiveTopology},
         render_asset::RenderAssetUsages,
@@ -20,24 +25,193 @@ use bevy::{
 };
 
 #[cfg(not(feature = "bevy"))]
-use glam::DAffine3;
+use glam::{DAffine3, DVec3};
 
 pub type MaterialIndex = usize;
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct MeshData {
     pub polygons: Vec<Polygon>,
 }
 
+/// Settings for [`MeshData::smooth_normals`].
+///
+/// # Fields
+/// * `max_angle` - The largest angle in radians between two face normals that still gets smoothed
+/// * `same_material` - Only smooth across polygons that share a material index
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "bevy", derive(bevy::prelude::Reflect))]
+pub struct SmoothNormalsSettings {
+    pub max_angle: f64,
+    pub same_material: bool,
+}
+
+impl Default for SmoothNormalsSettings {
+    fn default() -> Self {
+        Self {
+            max_angle: 60f64.to_radians(),
+            same_material: true,
+        }
+    }
+}
+
 impl MeshData {
+    /// Replaces the flat vertex normals with smoothed ones.
+    ///
+    /// Every vertex averages the face normals of the polygons touching its
+    /// position whose normal is within `max_angle` of its own polygon, weighted
+    /// by the corner angle of each polygon at that position. Hard edges above
+    /// the threshold keep the flat face normal.
+    pub fn smooth_normals(&mut self, settings: SmoothNormalsSettings) {
+        self.smooth_normals_with_tolerance(settings, &Tolerance::default());
+    }
+
+    /// Like [`MeshData::smooth_normals`], with the tolerance used to find shared positions.
+    pub fn smooth_normals_with_tolerance(
+        &mut self,
+        settings: SmoothNormalsSettings,
+        tolerance: &Tolerance,
+    ) {
+        let min_cos = settings.max_angle.cos() - Tolerance::NORMAL_EPSILON;
+        let key = |pos: DVec3| {
+            let cell = (pos / tolerance.epsilon).round();
+            [cell.x as i64, cell.y as i64, cell.z as i64]
+        };
+
+        // Every polygon corner at a position, as (face normal, material, corner angle)
+        let mut corners: HashMap<[i64; 3], Vec<(DVec3, MaterialIndex, f64)>> = HashMap::new();
+        for polygon in &self.polygons {
+            let count = polygon.vertices.len();
+            for (i, vertex) in polygon.vertices.iter().enumerate() {
+                let prev = polygon.vertices[(i + count - 1) % count].pos - vertex.pos;
+                let next = polygon.vertices[(i + 1) % count].pos - vertex.pos;
+                corners.entry(key(vertex.pos)).or_default().push((
+                    polygon.surface.normal,
+                    polygon.surface.material_idx,
+                    prev.angle_between(next),
+                ));
+            }
+        }
+
+        for polygon in &mut self.polygons {
+            let face_normal = polygon.surface.normal;
+            let material_idx = polygon.surface.material_idx;
+            for vertex in &mut polygon.vertices {
+                let normal: DVec3 = corners[&key(vertex.pos)]
+                    .iter()
+                    .filter(|(normal, material, _)| {
+                        normal.dot(face_normal) >= min_cos
+                            && (!settings.same_material || *material == material_idx)
+                    })
+                    .map(|(normal, _, weight)| *normal * *weight)
+                    .sum();
+                vertex.normal = normal.try_normalize().unwrap_or(face_normal);
+            }
+        }
+    }
+
+    /// Removes T-junctions by inserting a vertex into every polygon edge that
+    /// passes through a vertex of another polygon.
+    ///
+    /// CSG splits polygons independently of their neighbours, so one side of an
+    /// edge can end half way along the other side, which shows up as cracks once
+    /// rendered. Together with [`Polygon::indices`] this gives a watertight triangulation.
+    pub fn repair_t_junctions(&mut self) {
+        self.repair_t_junctions_with_tolerance(&Tolerance::default());
+    }
+
+    /// Like [`MeshData::repair_t_junctions`], with the tolerance used to find
+    /// positions on edges.
+    pub fn repair_t_junctions_with_tolerance(&mut self, tolerance: &Tolerance) {
+        let epsilon = tolerance.epsilon;
+        // Every distinct vertex position, sorted along x so each edge only
+        // checks the positions within its own extent
+        let mut positions: Vec<DVec3> = self
+            .polygons
+            .iter()
+            .flat_map(|polygon| polygon.vertices.iter().map(|vertex| vertex.pos))
+            .collect();
+        positions.sort_by(|a, b| {
+            a.x.total_cmp(&b.x)
+                .then(a.y.total_cmp(&b.y))
+                .then(a.z.total_cmp(&b.z))
+        });
+        positions.dedup_by(|a, b| a.distance_squared(*b) < epsilon * epsilon);
+
+        for polygon in &mut self.polygons {
+            let count = polygon.vertices.len();
+            let mut vertices = Vec::with_capacity(count);
+            for i in 0..count {
+                let start = &polygon.vertices[i];
+                let end = &polygon.vertices[(i + 1) % count];
+                vertices.push(start.clone());
+
+                let edge = end.pos - start.pos;
+                let length_squared = edge.length_squared();
+                let length = length_squared.sqrt();
+                if length < epsilon {
+                    continue;
+                }
+                let min_x = start.pos.x.min(end.pos.x) - epsilon;
+                let max_x = start.pos.x.max(end.pos.x) + epsilon;
+                let first = positions.partition_point(|pos| pos.x < min_x);
+
+                let mut splits: Vec<f64> = positions[first..]
+                    .iter()
+                    .take_while(|pos| pos.x <= max_x)
+                    .filter_map(|pos| {
+                        let t = (*pos - start.pos).dot(edge) / length_squared;
+                        let on_edge =
+                            (start.pos + edge * t).distance_squared(*pos) < epsilon * epsilon;
+                        // Points at the ends of the edge are already vertices
+                        let along = t * length;
+                        let inside = along > epsilon && along < length - epsilon;
+                        (on_edge && inside).then_some(t)
+                    })
+                    .collect();
+                splits.sort_by(f64::total_cmp);
+                splits.dedup_by(|a, b| (*a - *b) * length < epsilon);
+                vertices.extend(splits.into_iter().map(|t| start.interpolate(end, t)));
+            }
+            polygon.vertices = vertices;
+        }
+    }
+
+    /// Merges the polygons into one indexed batch per material, welding
+    /// vertices whose attributes match within `weld_tolerance`.
+    pub fn to_batched_mesh(&self, weld_tolerance: f64) -> BatchedMesh {
+        BatchedMesh::from_mesh_data(self, weld_tolerance)
+    }
+
+    /// Like [`MeshData::to_batched_mesh`], with the tolerance used to triangulate the polygons.
+    pub fn to_batched_mesh_with_tolerance(
+        &self,
+        weld_tolerance: f64,
+        tolerance: &Tolerance,
+    ) -> BatchedMesh {
+        BatchedMesh::from_mesh_data_with_tolerance(self, weld_tolerance, tolerance)
+    }
+
+    #[cfg(feature = "bevy")]
     pub fn to_bevy_meshes(&self) -> Vec<(Mesh, MaterialIndex)> {
+        self.to_bevy_meshes_with_tolerance(&Tolerance::default())
+    }
+
+    /// Like [`MeshData::to_bevy_meshes`], with the tolerance used to triangulate the polygons.
+    #[cfg(feature = "bevy")]
+    pub fn to_bevy_meshes_with_tolerance(
+        &self,
+        tolerance: &Tolerance,
+    ) -> Vec<(Mesh, MaterialIndex)> {
         let mut meshes_with_materials: Vec<(Mesh, MaterialIndex)> = vec![];
 
         for polygon in &self.polygons {
             let positions = polygon.positions_32();
             let normals = polygon.normals_32();
             let uvs = polygon.uvs();
-            let indices = polygon.indices();
+            let indices = polygon.indices_with_tolerance(tolerance);
             let mut mesh = Mesh::new(
                 PrimitiveTopology::TriangleList,
                 RenderAssetUsages::default(),
@@ -53,16 +227,63 @@ impl MeshData {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub enum BrushError {
     BrushletAtIndexDoesNotExist(usize),
+    /// An outline or polygon has fewer than 3 distinct points, no area or is not
+    /// flat, or a plane has no normal
+    DegeneratePolygon,
+    /// An outline or polygon that must be convex is not
+    NonConvex,
+    /// The brushlet has no face at the index
+    FaceAtIndexDoesNotExist(usize),
+    /// The operation needs the planes of a brushlet, but it only has baked polygons
+    NotPlaneDefined,
+    /// The operation would leave a solid without any volume
+    EmptySolid,
+    /// The planes leave the solid open in some direction
+    UnboundedSolid,
+    /// The planes at both indices are the same
+    DuplicatePlane(usize, usize),
+    /// The plane at the index doesn't touch the solid the other planes bound
+    RedundantPlane(usize),
+    /// A position, normal or distance is NaN or infinite
+    NonFiniteInput,
+}
+
+impl fmt::Display for BrushError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BrushError::BrushletAtIndexDoesNotExist(idx) => {
+                write!(f, "there is no brushlet at index {idx}")
+            }
+            BrushError::DegeneratePolygon => write!(f, "a polygon or plane is degenerate"),
+            BrushError::NonConvex => write!(f, "a polygon or outline is not convex"),
+            BrushError::FaceAtIndexDoesNotExist(idx) => {
+                write!(f, "the brushlet has no face at index {idx}")
+            }
+            BrushError::NotPlaneDefined => write!(f, "the brushlet is not defined by planes"),
+            BrushError::EmptySolid => write!(f, "the solid has no volume"),
+            BrushError::UnboundedSolid => write!(f, "the planes do not enclose a solid"),
+            BrushError::DuplicatePlane(a, b) => write!(f, "planes {a} and {b} are the same"),
+            BrushError::RedundantPlane(idx) => {
+                write!(f, "plane {idx} does not touch the solid")
+            }
+            BrushError::NonFiniteInput => write!(f, "the input contains NaN or infinite values"),
+        }
+    }
 }
 
+impl std::error::Error for BrushError {}
+
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[cfg_attr(feature = "bevy", derive(bevy::prelude::Reflect))]
 pub struct BrushSettings {
     pub name: String,
     pub knives: Vec<Knife>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub tolerance: Tolerance,
 }
 
 #[derive(Debug)]
@@ -78,13 +299,15 @@ pub struct BrushSelection {
 ///
 /// # Fields
 /// * `Knife` - A knife operation, slices the brushlet with a plane, disarding the part in front of the plane.
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum BrushletOp {
     Knife(Knife),
 }
 
 /// A boolean operation to perform between two brushlets.
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[cfg_attr(feature = "bevy", derive(bevy::prelude::Reflect))]
 pub enum BooleanOp {
     Union,
@@ -100,6 +323,7 @@ pub enum BooleanOp {
 /// * `brushlets` - The brushlets that make up the brush
 /// * `knives` - The knives to use for cutting
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[cfg_attr(
     feature = "bevy",
     derive(bevy::prelude::Component, bevy::prelude::Reflect)
@@ -116,6 +340,7 @@ impl Brush {
             settings: BrushSettings {
                 name: name.to_string(),
                 knives: Vec::new(),
+                tolerance: Tolerance::default(),
             },
         }
     }
@@ -124,7 +349,9 @@ impl Brush {
         let mut closest = None;
         let mut closest_distance = f64::INFINITY;
         for brushlet in self.brushlets.iter() {
-            if let Some(result) = brushlet.try_select(&raycast) {
+            if let Some(result) =
+                brushlet.try_select_with_tolerance(raycast, &self.settings.tolerance)
+            {
                 if result.distance < closest_distance {
                     closest_distance = result.distance;
                     closest = Some(result);
@@ -138,7 +365,9 @@ impl Brush {
         let mut closest = None;
         let mut closest_distance = f64::INFINITY;
         for (idx, brushlet) in self.brushlets.iter().enumerate() {
-            if let Some(result) = brushlet.try_select(&raycast) {
+            if let Some(result) =
+                brushlet.try_select_with_tolerance(raycast, &self.settings.tolerance)
+            {
                 if result.distance < closest_distance {
                     closest_distance = result.distance;
                     closest = Some(idx);
@@ -169,35 +398,214 @@ impl Brush {
             };
         }
 
+        let tolerance = &self.settings.tolerance;
         let mut final_brushlet = self.brushlets[0].clone();
 
         for other in self.brushlets.iter().skip(1) {
             final_brushlet = match other.settings.operation {
-                BooleanOp::Union => final_brushlet.union(other),
-                BooleanOp::Intersect => final_brushlet.intersect(other),
-                BooleanOp::Subtract => final_brushlet.subtract(other),
+                BooleanOp::Union => final_brushlet.union_with_tolerance(other, tolerance),
+                BooleanOp::Intersect => final_brushlet.intersect_with_tolerance(other, tolerance),
+                BooleanOp::Subtract => final_brushlet.subtract_with_tolerance(other, tolerance),
             };
         }
 
         // do the final global knife operations
         for knife in &self.settings.knives {
-            final_brushlet = knife.perform(&final_brushlet);
+            final_brushlet = knife.perform_with_tolerance(&final_brushlet, tolerance);
         }
 
-        final_brushlet.to_mesh_data()
+        final_brushlet.to_mesh_data_with_tolerance(tolerance)
     }
 
     pub fn compute_transform(&self) -> DAffine3 {
         let mut transform = DAffine3::IDENTITY;
         for brushlet in &self.brushlets {
-            transform = transform * brushlet.compute_transform();
+            transform *= brushlet.compute_transform();
         }
         transform
     }
 
     pub fn transform(&mut self, transform: DAffine3) {
         for brushlet in &mut self.brushlets {
-            brushlet.transform(transform);
+            *brushlet = brushlet.transform_with_tolerance(transform, &self.settings.tolerance);
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{polygon::Vertex, surface::Surface};
+
+    fn quad(corners: [DVec3; 4], material_idx: MaterialIndex) -> Polygon {
+        let surface = Surface::from_points(corners[0], corners[1], corners[2], material_idx);
+        Polygon {
+            vertices: corners
+                .into_iter()
+                .map(|pos| Vertex::new(pos, surface.normal))
+                .collect(),
+            surface,
+        }
+    }
+
+    #[test]
+    fn test_smooth_normals() {
+        // Two faces meeting at a right angle along the z axis
+        let mesh_data = MeshData {
+            polygons: vec![
+                quad(
+                    [
+                        DVec3::new(0.0, 0.0, 0.0),
+                        DVec3::new(0.0, 0.0, 1.0),
+                        DVec3::new(-1.0, 0.0, 1.0),
+                        DVec3::new(-1.0, 0.0, 0.0),
+                    ],
+                    0,
+                ),
+                quad(
+                    [
+                        DVec3::new(0.0, 0.0, 0.0),
+                        DVec3::new(0.0, -1.0, 0.0),
+                        DVec3::new(0.0, -1.0, 1.0),
+                        DVec3::new(0.0, 0.0, 1.0),
+                    ],
+                    1,
+                ),
+            ],
+        };
+        let normals = |mesh_data: &MeshData| -> Vec<DVec3> {
+            mesh_data
+                .polygons
+                .iter()
+                .flat_map(|polygon| polygon.normals())
+                .collect()
+        };
+        let flat = normals(&mesh_data);
+
+        let mut sharp = mesh_data.clone();
+        sharp.smooth_normals(SmoothNormalsSettings {
+            max_angle: 80f64.to_radians(),
+            same_material: false,
+        });
+        assert_eq!(normals(&sharp), flat);
+
+        let mut different_materials = mesh_data.clone();
+        different_materials.smooth_normals(SmoothNormalsSettings {
+            max_angle: 100f64.to_radians(),
+            same_material: true,
+        });
+        assert_eq!(normals(&different_materials), flat);
+
+        let mut smooth = mesh_data.clone();
+        smooth.smooth_normals(SmoothNormalsSettings {
+            max_angle: 100f64.to_radians(),
+            same_material: false,
+        });
+        let shared = (flat[0] + flat[4]).normalize();
+        for polygon in &smooth.polygons {
+            for vertex in &polygon.vertices {
+                if vertex.pos.x == 0.0 && vertex.pos.y == 0.0 {
+                    assert!(vertex.normal.abs_diff_eq(shared, 1e-9));
+                } else {
+                    assert_eq!(vertex.normal, polygon.surface.normal);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_repair_t_junctions() {
+        // One quad above two smaller ones, the middle of its bottom edge is a T-junction
+        let point = |x: f64, z: f64| DVec3::new(x, 0.0, z);
+        let mut mesh_data = MeshData {
+            polygons: vec![
+                quad(
+                    [
+                        point(0.0, 0.0),
+                        point(0.0, 1.0),
+                        point(2.0, 1.0),
+                        point(2.0, 0.0),
+                    ],
+                    0,
+                ),
+                quad(
+                    [
+                        point(0.0, -1.0),
+                        point(0.0, 0.0),
+                        point(1.0, 0.0),
+                        point(1.0, -1.0),
+                    ],
+                    0,
+                ),
+                quad(
+                    [
+                        point(1.0, -1.0),
+                        point(1.0, 0.0),
+                        point(2.0, 0.0),
+                        point(2.0, -1.0),
+                    ],
+                    0,
+                ),
+            ],
+        };
+        mesh_data.repair_t_junctions();
+
+        assert_eq!(mesh_data.polygons[0].vertices.len(), 5);
+        assert!(mesh_data.polygons[0].vertices[4]
+            .pos
+            .abs_diff_eq(point(1.0, 0.0), 1e-9));
+        assert_eq!(mesh_data.polygons[1].vertices.len(), 4);
+        assert_eq!(mesh_data.polygons[2].vertices.len(), 4);
+
+        // Every edge along the seam is shared by exactly two triangles
+        let mut seam_edges: HashMap<[i64; 2], usize> = HashMap::new();
+        let mut area = 0.0;
+        for polygon in &mesh_data.polygons {
+            for triangle in polygon.indices().chunks(3) {
+                let [a, b, c] = [0, 1, 2].map(|i| polygon.vertices[triangle[i] as usize].pos);
+                let cross = (b - a).cross(c - a);
+                assert!(cross.dot(polygon.surface.normal) > 0.0);
+                area += cross.length() / 2.0;
+                for (start, end) in [(a, b), (b, c), (c, a)] {
+                    if start.z.abs() < 1e-9 && end.z.abs() < 1e-9 {
+                        let mut key = [start.x.round() as i64, end.x.round() as i64];
+                        key.sort();
+                        *seam_edges.entry(key).or_default() += 1;
+                    }
+                }
+            }
+        }
+        assert!((area - 4.0).abs() < 1e-9);
+        assert_eq!(seam_edges, HashMap::from([([0, 1], 2), ([1, 2], 2)]));
+    }
+
+    #[test]
+    fn test_triangulate_small_polygon() {
+        // A 3 mm face with a vertex in the middle of its bottom edge
+        let size = 0.003;
+        let point = |x: f64, z: f64| DVec3::new(x * size, 0.0, z * size);
+        let mut polygon = quad(
+            [
+                point(0.0, 0.0),
+                point(0.0, 1.0),
+                point(1.0, 1.0),
+                point(1.0, 0.0),
+            ],
+            0,
+        );
+        polygon
+            .vertices
+            .push(Vertex::new(point(0.5, 0.0), polygon.surface.normal));
+
+        let indices = polygon.indices();
+        assert_eq!(indices.len(), 9);
+        let area: f64 = indices
+            .chunks(3)
+            .map(|triangle| {
+                let [a, b, c] = [0, 1, 2].map(|i| polygon.vertices[triangle[i] as usize].pos);
+                (b - a).cross(c - a).length() / 2.0
+            })
+            .sum();
+        assert!((area - size * size).abs() < 1e-12);
+    }
+}
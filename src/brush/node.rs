@@ -1,4 +1,4 @@
-use crate::{polygon::Polygon, surface::Surface};
+use crate::{polygon::Polygon, surface::Surface, tolerance::Tolerance};
 
 #[derive(Debug, Clone)]
 pub(crate) struct Node {
@@ -6,15 +6,17 @@ pub(crate) struct Node {
     front: Option<Box<Node>>,
     back: Option<Box<Node>>,
     polygons: Vec<Polygon>,
+    tolerance: Tolerance,
 }
 
 impl Node {
-    pub fn new(polygons: Vec<Polygon>) -> Self {
+    pub fn new(polygons: Vec<Polygon>, tolerance: Tolerance) -> Self {
         let mut node = Self {
             plane: None,
             front: None,
             back: None,
             polygons: Vec::new(),
+            tolerance,
         };
         node.build(polygons);
         node
@@ -45,8 +47,11 @@ impl Node {
         let mut back = Vec::new();
 
         for polygon in polygons {
-            let (mut cp_front, mut cp_back, mut f, mut b) =
-                self.plane.as_ref().unwrap().split_polygon(&polygon);
+            let (mut cp_front, mut cp_back, mut f, mut b) = self
+                .plane
+                .as_ref()
+                .unwrap()
+                .split_polygon_with_tolerance(&polygon, &self.tolerance);
             front.append(&mut cp_front);
             front.append(&mut f);
             back.append(&mut cp_back);
@@ -93,14 +98,15 @@ impl Node {
             return;
         }
         if self.plane.is_none() {
-            self.plane = Some(polygons[0].surface.clone());
+            self.plane = Some(polygons[0].surface);
         }
         let plane = self.plane.as_ref().unwrap();
         let mut front = Vec::new();
         let mut back = Vec::new();
 
         for polygon in polygons.drain(..) {
-            let (mut cp_front, mut cp_back, mut f, mut b) = plane.split_polygon(&polygon);
+            let (mut cp_front, mut cp_back, mut f, mut b) =
+                plane.split_polygon_with_tolerance(&polygon, &self.tolerance);
             self.polygons.append(&mut cp_front);
             self.polygons.append(&mut cp_back);
             front.append(&mut f);
@@ -109,14 +115,14 @@ impl Node {
 
         if !front.is_empty() {
             if self.front.is_none() {
-                self.front = Some(Box::new(Node::new(Vec::new())));
+                self.front = Some(Box::new(Node::new(Vec::new(), self.tolerance)));
             }
             self.front.as_mut().unwrap().build(front);
         }
 
         if !back.is_empty() {
             if self.back.is_none() {
-                self.back = Some(Box::new(Node::new(Vec::new())));
+                self.back = Some(Box::new(Node::new(Vec::new(), self.tolerance)));
             }
             self.back.as_mut().unwrap().build(back);
         }
@@ -4,71 +4,426 @@ use bevy::math::{DAffine3, DVec3};
 #[cfg(not(feature = "bevy"))]
 use glam::{DAffine3, DVec3};
 
-use super::brushlet::Brushlet;
-use crate::surface::Surface;
+use super::{
+    brushlet::{Brushlet, BrushletSettings},
+    BooleanOp, Brush, BrushError,
+};
+use crate::{broadphase::Aabb, polygon::Polygon, surface::Surface, tolerance::Tolerance};
 
 /// A knife
 ///
-/// A knife is a plane that is used to cut geometry.
+/// A knife is a plane that is used to cut geometry, discarding the part in front of it.
+///
+/// Extra `profile` planes make a compound knife, only the part in front of every
+/// plane is discarded, so two planes meeting at an angle cut a V-notch. The cut can
+/// be limited to the convex region enclosed by the `bounds` planes to make partial cuts.
 ///
 /// # Fields
 /// * `normal` - The normal of the plane
 /// * `distance_from_origin` - The distance from the origin of the geometry
-#[derive(Debug, Clone, Copy)]
+/// * `material_index` - The material index of the cut faces
+/// * `profile` - Extra planes of a compound knife, their material indices are used for their cut faces
+/// * `bounds` - Planes facing out of the region the cut applies within, unbounded when empty
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[cfg_attr(feature = "bevy", derive(bevy::prelude::Reflect))]
 pub struct Knife {
     pub normal: DVec3,
     pub distance_from_origin: f64,
     pub material_index: usize,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub profile: Vec<Surface>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub bounds: Vec<Surface>,
 }
 
 impl Knife {
+    pub fn new(normal: DVec3, distance_from_origin: f64, material_index: usize) -> Self {
+        Self {
+            normal,
+            distance_from_origin,
+            material_index,
+            profile: Vec::new(),
+            bounds: Vec::new(),
+        }
+    }
+
+    /// Adds a plane to the knife's profile.
+    pub fn with_profile(mut self, surface: Surface) -> Self {
+        self.profile.push(unit_plane(surface));
+        self
+    }
+
+    /// Limits the cut to the convex region enclosed by the planes.
+    pub fn with_bounds(mut self, bounds: Vec<Surface>) -> Self {
+        self.bounds = bounds.into_iter().map(unit_plane).collect();
+        self
+    }
+
+    /// Limits the cut to the inside of a bounding box, its walls get the knife's material.
+    pub fn with_bounds_aabb(self, aabb: Aabb) -> Self {
+        let material_index = self.material_index;
+        self.with_bounds(aabb_surfaces(aabb, material_index))
+    }
+
+    /// Whether the knife is a single unbounded plane.
+    pub fn is_plane(&self) -> bool {
+        self.profile.is_empty() && self.bounds.is_empty()
+    }
+
+    /// The knife plane followed by the profile planes, normalized.
+    pub fn planes(&self) -> Vec<Surface> {
+        std::iter::once(Surface::new(
+            self.normal,
+            self.distance_from_origin,
+            self.material_index,
+        ))
+        .chain(self.profile.iter().copied())
+        .map(unit_plane)
+        .collect()
+    }
+
+    /// The bounds planes, normalized.
+    pub fn bound_planes(&self) -> Vec<Surface> {
+        self.bounds.iter().copied().map(unit_plane).collect()
+    }
+
     pub fn perform(&self, brushlet: &Brushlet) -> Brushlet {
-        // Define a large value to ensure the cuboid encompasses the entire geometry
-        const LARGE_VALUE: f64 = 1e5;
+        self.perform_with_tolerance(brushlet, &Tolerance::default())
+    }
 
-        // Create the primary cutting plane
-        let cutting_plane = Surface::new(
-            -self.normal,
-            -self.distance_from_origin,
+    pub fn perform_with_tolerance(&self, brushlet: &Brushlet, tolerance: &Tolerance) -> Brushlet {
+        if brushlet.polygons.is_empty() {
+            return brushlet.clone();
+        }
+
+        let planes = self.planes();
+
+        // Plane defined brushlets only need one more plane, which keeps every
+        // other face as it is
+        if let (Some(surfaces), true) = (&brushlet.surfaces, self.is_plane()) {
+            let mut surfaces = surfaces.clone();
+            surfaces.extend(planes);
+            return Brushlet::from_surfaces_with_tolerance(
+                surfaces,
+                brushlet.settings.clone(),
+                tolerance,
+            );
+        }
+
+        // The discarded region is in front of every knife plane and inside the
+        // bounds, closed off by a box a little larger than the brushlet
+        let margin = (brushlet.aabb.max - brushlet.aabb.min).length().max(1.0);
+        let mut region: Vec<Surface> = planes
+            .into_iter()
+            .map(|mut surface| {
+                surface.flip();
+                surface
+            })
+            .collect();
+        region.extend(self.bound_planes());
+        region.extend(aabb_surfaces(
+            Aabb::new(
+                brushlet.aabb.min - DVec3::splat(margin),
+                brushlet.aabb.max + DVec3::splat(margin),
+            ),
             self.material_index,
-        );
+        ));
 
-        // Create two orthogonal vectors to the plane normal
-        let mut u = if self.normal.x.abs() > self.normal.y.abs() {
-            DVec3::new(0.0, 1.0, 0.0)
-        } else {
-            DVec3::new(1.0, 0.0, 0.0)
-        };
-        u = u.cross(self.normal).normalize();
-        let v = self.normal.cross(u).normalize();
-
-        // Create the six planes that define the cutting cuboid
-        let planes = vec![
-            cutting_plane,
-            Surface::new(
-                self.normal,
-                self.distance_from_origin + LARGE_VALUE,
-                self.material_index,
-            ), // Back plane, far behind the cut
-            Surface::new(u, LARGE_VALUE, self.material_index), // Large plane in one direction
-            Surface::new(-u, LARGE_VALUE, self.material_index), // Large plane in the opposite direction
-            Surface::new(v, LARGE_VALUE, self.material_index),  // Large plane in another direction
-            Surface::new(-v, LARGE_VALUE, self.material_index), // Large plane in the opposite direction
-        ];
-
-        // Create the cutting cuboid from the defined planes
-        let cutting_cuboid = Brushlet::from_surfaces(planes, brushlet.settings.clone());
-        brushlet.subtract(&cutting_cuboid)
+        let cutting_solid =
+            Brushlet::from_surfaces_with_tolerance(region, brushlet.settings.clone(), tolerance);
+        if cutting_solid.polygons.is_empty() {
+            return brushlet.clone();
+        }
+        brushlet.subtract_with_tolerance(&cutting_solid, tolerance)
     }
 
     pub fn transform(&self, transform: DAffine3) -> Self {
-        let normal = transform.transform_vector3(self.normal).normalize();
-        let distance_from_origin = self.distance_from_origin + normal.dot(transform.translation);
+        let mut planes = self
+            .planes()
+            .into_iter()
+            .map(|surface| surface.transform(transform));
+        let plane = planes.next().unwrap();
         Self {
-            normal,
-            distance_from_origin,
+            normal: plane.normal,
+            distance_from_origin: plane.distance_from_origin,
             material_index: self.material_index,
+            profile: planes.collect(),
+            bounds: self
+                .bound_planes()
+                .into_iter()
+                .map(|surface| surface.transform(transform))
+                .collect(),
+        }
+    }
+}
+
+/// The plane of a surface with a unit normal.
+///
+/// Knives may be given unnormalized, the cut faces should still get a unit
+/// normal and [`Surface::transform`] expects one.
+fn unit_plane(surface: Surface) -> Surface {
+    let length = surface.normal.length();
+    Surface {
+        normal: surface.normal / length,
+        distance_from_origin: surface.distance_from_origin / length,
+        ..surface
+    }
+}
+
+/// The six outward facing planes of a bounding box.
+fn aabb_surfaces(aabb: Aabb, material_index: usize) -> Vec<Surface> {
+    [DVec3::X, DVec3::Y, DVec3::Z]
+        .into_iter()
+        .flat_map(|axis| {
+            [
+                Surface::new(axis, axis.dot(aabb.max), material_index),
+                Surface::new(-axis, -axis.dot(aabb.min), material_index),
+            ]
+        })
+        .collect()
+}
+
+/// The edges of a brushlet to bevel
+///
+/// # Variants
+/// * `All` - Every edge
+/// * `Vertical` - Edges running along the Y axis
+/// * `Faces` - The edges between pairs of faces, given as indices into the brushlet's planes
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum BevelEdges {
+    All,
+    Vertical,
+    Faces(Vec<(usize, usize)>),
+}
+
+/// A bevel
+///
+/// Rounds off edges of a plane defined brushlet by adding faces tangent to a
+/// fillet. With a single segment this is a flat chamfer.
+///
+/// # Fields
+/// * `edges` - The edges to bevel
+/// * `distance` - How far the bevel reaches from the edge along each face
+/// * `segments` - The number of faces across each bevel, at least 1
+/// * `material_index` - The material index for the new faces
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Bevel {
+    pub edges: BevelEdges,
+    pub distance: f64,
+    pub segments: usize,
+    pub material_index: usize,
+}
+
+impl Bevel {
+    pub fn perform(&self, brushlet: &Brushlet) -> Result<Brushlet, BrushError> {
+        self.perform_with_tolerance(brushlet, &Tolerance::default())
+    }
+
+    pub fn perform_with_tolerance(
+        &self,
+        brushlet: &Brushlet,
+        tolerance: &Tolerance,
+    ) -> Result<Brushlet, BrushError> {
+        let surfaces = brushlet
+            .surfaces
+            .as_ref()
+            .ok_or(BrushError::NotPlaneDefined)?;
+
+        let edges = match &self.edges {
+            BevelEdges::Faces(faces) => {
+                for (a, b) in faces {
+                    for idx in [*a, *b] {
+                        if idx >= surfaces.len() {
+                            return Err(BrushError::FaceAtIndexDoesNotExist(idx));
+                        }
+                    }
+                }
+                faces.clone()
+            }
+            BevelEdges::All => adjacent_faces(brushlet, surfaces, tolerance),
+            BevelEdges::Vertical => adjacent_faces(brushlet, surfaces, tolerance)
+                .into_iter()
+                .filter(|(a, b)| {
+                    let direction = surfaces[*a].normal.cross(surfaces[*b].normal).normalize();
+                    direction.y.abs() > 1.0 - Tolerance::NORMAL_EPSILON
+                })
+                .collect(),
+        };
+
+        let segments = self.segments.max(1);
+        let mut beveled = surfaces.clone();
+        for (a, b) in edges {
+            // Planes with unit normals
+            let (n_a, d_a) = (
+                surfaces[a].normal.normalize(),
+                surfaces[a].distance_from_origin / surfaces[a].normal.length(),
+            );
+            let (n_b, d_b) = (
+                surfaces[b].normal.normalize(),
+                surfaces[b].distance_from_origin / surfaces[b].normal.length(),
+            );
+            let angle = n_a.angle_between(n_b);
+            if !(Tolerance::NORMAL_EPSILON..=std::f64::consts::PI - Tolerance::NORMAL_EPSILON)
+                .contains(&angle)
+            {
+                continue;
+            }
+
+            // The fillet touches both faces `distance` away from the edge
+            let radius = self.distance / (angle * 0.5).tan();
+            for m in 0..segments {
+                let t = (m as f64 + 0.5) / segments as f64;
+                let weight_a = ((1.0 - t) * angle).sin() / angle.sin();
+                let weight_b = (t * angle).sin() / angle.sin();
+                let normal = n_a * weight_a + n_b * weight_b;
+                // The faces' corners lie on the fillet
+                let center_distance = weight_a * (d_a - radius) + weight_b * (d_b - radius);
+                let distance = center_distance + radius * (angle * 0.5 / segments as f64).cos();
+                beveled.push(Surface::new(normal, distance, self.material_index));
+            }
+        }
+
+        let result =
+            Brushlet::from_surfaces_with_tolerance(beveled, brushlet.settings.clone(), tolerance);
+        if result.polygons.len() < 4 {
+            return Err(BrushError::EmptySolid);
+        }
+        Ok(result)
+    }
+}
+
+/// How the walls of a hollowed brushlet meet at its edges
+///
+/// # Variants
+/// * `Mitered` - Walls are cut where they meet, so they never overlap
+/// * `Overlapping` - Every wall covers its whole face, overlapping its neighbours at the edges
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum HollowJoint {
+    Mitered,
+    Overlapping,
+}
+
+/// A hollow
+///
+/// Turns a plane defined brushlet into a shell of walls, one per face, that
+/// enclose the original volume. The outside of every wall keeps its face.
+///
+/// # Fields
+/// * `thickness` - The thickness of the walls
+/// * `joint` - How the walls meet at the edges
+/// * `interior_material_index` - The material index for the inside of the walls
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Hollow {
+    pub thickness: f64,
+    pub joint: HollowJoint,
+    pub interior_material_index: usize,
+}
+
+impl Hollow {
+    pub fn perform(&self, brushlet: &Brushlet) -> Result<Brush, BrushError> {
+        self.perform_with_tolerance(brushlet, &Tolerance::default())
+    }
+
+    pub fn perform_with_tolerance(
+        &self,
+        brushlet: &Brushlet,
+        tolerance: &Tolerance,
+    ) -> Result<Brush, BrushError> {
+        let surfaces = brushlet
+            .surfaces
+            .as_ref()
+            .ok_or(BrushError::NotPlaneDefined)?;
+        if self.thickness <= 0.0 {
+            return Err(BrushError::EmptySolid);
+        }
+
+        let unit: Vec<Surface> = surfaces.iter().copied().map(unit_plane).collect();
+
+        let mut brush = Brush::new(&brushlet.settings.name);
+        for (i, face) in unit.iter().enumerate() {
+            let mut planes = unit.clone();
+            planes.push(Surface::new(
+                -face.normal,
+                -(face.distance_from_origin - self.thickness),
+                self.interior_material_index,
+            ));
+            if self.joint == HollowJoint::Mitered {
+                // Keep the points that are closer to this face than to the other one
+                for (j, other) in unit.iter().enumerate() {
+                    let normal = other.normal - face.normal;
+                    let length = normal.length();
+                    if j == i || length < Tolerance::NORMAL_EPSILON {
+                        continue;
+                    }
+                    planes.push(Surface::new(
+                        normal / length,
+                        (other.distance_from_origin - face.distance_from_origin) / length,
+                        self.interior_material_index,
+                    ));
+                }
+            }
+
+            let wall = Brushlet::from_surfaces_with_tolerance(
+                planes,
+                BrushletSettings {
+                    name: format!("Wall {}", i + 1),
+                    operation: BooleanOp::Union,
+                    knives: Vec::new(),
+                    inverted: false,
+                },
+                tolerance,
+            );
+            if wall.polygons.len() >= 4 {
+                brush.brushlets.push(wall);
+            }
+        }
+
+        if brush.brushlets.is_empty() {
+            return Err(BrushError::EmptySolid);
+        }
+        Ok(brush)
+    }
+}
+
+/// The pairs of faces, as indices into `surfaces`, that share an edge.
+fn adjacent_faces(
+    brushlet: &Brushlet,
+    surfaces: &[Surface],
+    tolerance: &Tolerance,
+) -> Vec<(usize, usize)> {
+    let faces: Vec<(usize, &Polygon)> = brushlet
+        .polygons
+        .iter()
+        .filter_map(|polygon| {
+            surfaces
+                .iter()
+                .position(|surface| tolerance.same_plane(surface, &polygon.surface))
+                .map(|idx| (idx, polygon))
+        })
+        .collect();
+
+    let mut pairs = Vec::new();
+    for (i, (a, polygon_a)) in faces.iter().enumerate() {
+        for (b, polygon_b) in faces.iter().skip(i + 1) {
+            let shared = polygon_a
+                .vertices
+                .iter()
+                .filter(|vertex| {
+                    polygon_b
+                        .vertices
+                        .iter()
+                        .any(|other| tolerance.same_point(other.pos, vertex.pos))
+                })
+                .count();
+            if shared >= 2 {
+                pairs.push((*a, *b));
+            }
         }
     }
+    pairs
 }
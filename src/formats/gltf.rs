@@ -0,0 +1,517 @@
+//! glTF 2.0 files, either as `.gltf` JSON with an embedded buffer or as binary `.glb`.
+//!
+//! Every brush becomes a node with one mesh, holding one indexed triangle
+//! primitive per material index. Scenes get one parent node per [`Layer`].
+
+use std::{collections::HashMap, fmt::Write};
+
+use crate::{
+    brush::{Brush, MaterialIndex},
+    polygon::Polygon,
+    scene::{BrusherScene, Layer},
+    tolerance::Tolerance,
+};
+
+const ARRAY_BUFFER: u32 = 34962;
+const ELEMENT_ARRAY_BUFFER: u32 = 34963;
+const FLOAT: u32 = 5126;
+const UNSIGNED_INT: u32 = 5125;
+
+/// Settings for glTF export.
+///
+/// # Fields
+/// * `material_names` - Names of the glTF materials, indexed by material index
+/// * `tangents` - Whether to write tangents derived from the surface texture axes
+#[derive(Debug, Clone, Copy, Default)]
+pub struct GltfSettings<'a> {
+    pub material_names: &'a [&'a str],
+    pub tangents: bool,
+}
+
+/// Writes a brush as a `.gltf` file with an embedded buffer.
+pub fn brush_to_gltf(brush: &Brush, settings: &GltfSettings) -> String {
+    let mut builder = Builder::new(settings);
+    let node = builder.add_brush(brush);
+    builder.to_gltf(&[node])
+}
+
+/// Writes a brush as a binary `.glb` file.
+pub fn brush_to_glb(brush: &Brush, settings: &GltfSettings) -> Vec<u8> {
+    let mut builder = Builder::new(settings);
+    let node = builder.add_brush(brush);
+    builder.to_glb(&[node])
+}
+
+/// Writes a scene as a `.gltf` file with an embedded buffer.
+pub fn scene_to_gltf(scene: &BrusherScene, settings: &GltfSettings) -> String {
+    let mut builder = Builder::new(settings);
+    let nodes: Vec<usize> = scene
+        .layers
+        .iter()
+        .map(|layer| builder.add_layer(layer))
+        .collect();
+    builder.to_gltf(&nodes)
+}
+
+/// Writes a scene as a binary `.glb` file.
+pub fn scene_to_glb(scene: &BrusherScene, settings: &GltfSettings) -> Vec<u8> {
+    let mut builder = Builder::new(settings);
+    let nodes: Vec<usize> = scene
+        .layers
+        .iter()
+        .map(|layer| builder.add_layer(layer))
+        .collect();
+    builder.to_glb(&nodes)
+}
+
+/// Collects the JSON objects of each glTF array and the binary buffer.
+struct Builder<'a> {
+    settings: &'a GltfSettings<'a>,
+    nodes: Vec<String>,
+    meshes: Vec<String>,
+    materials: Vec<String>,
+    material_lookup: HashMap<MaterialIndex, usize>,
+    accessors: Vec<String>,
+    buffer_views: Vec<String>,
+    buffer: Vec<u8>,
+}
+
+impl<'a> Builder<'a> {
+    fn new(settings: &'a GltfSettings<'a>) -> Self {
+        Self {
+            settings,
+            nodes: Vec::new(),
+            meshes: Vec::new(),
+            materials: Vec::new(),
+            material_lookup: HashMap::new(),
+            accessors: Vec::new(),
+            buffer_views: Vec::new(),
+            buffer: Vec::new(),
+        }
+    }
+
+    fn add_layer(&mut self, layer: &Layer) -> usize {
+        let children: Vec<usize> = layer
+            .brushes
+            .iter()
+            .map(|brush| self.add_brush(brush))
+            .collect();
+        let mut node = format!(
+            "{{\"name\":{},\"children\":{}",
+            json_string(&layer.name),
+            json_array(&children)
+        );
+        if layer.hidden {
+            node.push_str(",\"extras\":{\"hidden\":true}");
+        }
+        node.push('}');
+        self.nodes.push(node);
+        self.nodes.len() - 1
+    }
+
+    fn add_brush(&mut self, brush: &Brush) -> usize {
+        let name = json_string(&brush.settings.name);
+        let mesh_data = brush.to_mesh_data();
+
+        let mut primitives = Vec::new();
+        let mut material_order = Vec::new();
+        let mut groups: HashMap<MaterialIndex, Vec<&Polygon>> = HashMap::new();
+        for polygon in &mesh_data.polygons {
+            let material_idx = polygon.surface.material_idx;
+            if !groups.contains_key(&material_idx) {
+                material_order.push(material_idx);
+            }
+            groups.entry(material_idx).or_default().push(polygon);
+        }
+        for material_idx in material_order {
+            primitives.push(self.add_primitive(
+                material_idx,
+                &groups[&material_idx],
+                &brush.settings.tolerance,
+            ));
+        }
+
+        let node = if primitives.is_empty() {
+            format!("{{\"name\":{name}}}")
+        } else {
+            self.meshes.push(format!(
+                "{{\"name\":{name},\"primitives\":[{}]}}",
+                primitives.join(",")
+            ));
+            format!("{{\"name\":{name},\"mesh\":{}}}", self.meshes.len() - 1)
+        };
+        self.nodes.push(node);
+        self.nodes.len() - 1
+    }
+
+    fn add_primitive(
+        &mut self,
+        material_idx: MaterialIndex,
+        polygons: &[&Polygon],
+        tolerance: &Tolerance,
+    ) -> String {
+        let mut positions = Vec::new();
+        let mut normals = Vec::new();
+        let mut uvs = Vec::new();
+        let mut tangents = Vec::new();
+        let mut indices = Vec::new();
+
+        for polygon in polygons {
+            let offset = (positions.len() / 3) as u32;
+            indices.extend(
+                polygon
+                    .indices_with_tolerance(tolerance)
+                    .into_iter()
+                    .map(|i| i + offset),
+            );
+            positions.extend(polygon.positions_32().into_iter().flatten());
+            normals.extend(polygon.normals_32().into_iter().flatten());
+            uvs.extend(polygon.uvs().into_iter().flatten());
+            if self.settings.tangents {
+                let tangent = tangent(polygon);
+                for _ in &polygon.vertices {
+                    tangents.extend(tangent);
+                }
+            }
+        }
+
+        let mut min = [f32::INFINITY; 3];
+        let mut max = [f32::NEG_INFINITY; 3];
+        for position in positions.chunks(3) {
+            for axis in 0..3 {
+                min[axis] = min[axis].min(position[axis]);
+                max[axis] = max[axis].max(position[axis]);
+            }
+        }
+
+        let bounds = format!(",\"min\":{},\"max\":{}", json_array(&min), json_array(&max));
+        let position = self.add_accessor(&bits(&positions), FLOAT, "VEC3", &bounds);
+        let normal = self.add_accessor(&bits(&normals), FLOAT, "VEC3", "");
+        let uv = self.add_accessor(&bits(&uvs), FLOAT, "VEC2", "");
+
+        let mut attributes =
+            format!("\"POSITION\":{position},\"NORMAL\":{normal},\"TEXCOORD_0\":{uv}");
+        if self.settings.tangents {
+            let tangent = self.add_accessor(&bits(&tangents), FLOAT, "VEC4", "");
+            write!(attributes, ",\"TANGENT\":{tangent}").unwrap();
+        }
+        let indices = self.add_accessor(&indices, UNSIGNED_INT, "SCALAR", "");
+
+        format!(
+            "{{\"attributes\":{{{attributes}}},\"indices\":{indices},\"material\":{},\"mode\":4}}",
+            self.material(material_idx)
+        )
+    }
+
+    /// Appends 32 bit components to the buffer and returns the index of their accessor.
+    ///
+    /// `extra` is appended to the accessor object, e.g. its bounds.
+    fn add_accessor(
+        &mut self,
+        components: &[u32],
+        component_type: u32,
+        accessor_type: &str,
+        extra: &str,
+    ) -> usize {
+        let width = match accessor_type {
+            "VEC2" => 2,
+            "VEC3" => 3,
+            "VEC4" => 4,
+            _ => 1,
+        };
+        let target = if component_type == UNSIGNED_INT {
+            ELEMENT_ARRAY_BUFFER
+        } else {
+            ARRAY_BUFFER
+        };
+        let byte_offset = self.buffer.len();
+        for component in components {
+            self.buffer.extend_from_slice(&component.to_le_bytes());
+        }
+        self.buffer_views.push(format!(
+            "{{\"buffer\":0,\"byteOffset\":{byte_offset},\"byteLength\":{},\"target\":{target}}}",
+            components.len() * 4
+        ));
+        self.accessors.push(format!(
+            "{{\"bufferView\":{},\"componentType\":{component_type},\"count\":{},\"type\":\"{accessor_type}\"{extra}}}",
+            self.buffer_views.len() - 1,
+            components.len() / width
+        ));
+        self.accessors.len() - 1
+    }
+
+    /// Returns the glTF material for a material index, adding it on first use.
+    fn material(&mut self, material_idx: MaterialIndex) -> usize {
+        if let Some(material) = self.material_lookup.get(&material_idx) {
+            return *material;
+        }
+        let name = match self.settings.material_names.get(material_idx) {
+            Some(name) => name.to_string(),
+            None => format!("material_{material_idx}"),
+        };
+        self.materials.push(format!(
+            "{{\"name\":{},\"pbrMetallicRoughness\":{{\"baseColorFactor\":[1,1,1,1],\"metallicFactor\":0,\"roughnessFactor\":1}}}}",
+            json_string(&name)
+        ));
+        self.material_lookup
+            .insert(material_idx, self.materials.len() - 1);
+        self.materials.len() - 1
+    }
+
+    fn to_json(&self, root_nodes: &[usize], buffer_uri: Option<String>) -> String {
+        let mut json = String::from("{\"asset\":{\"version\":\"2.0\",\"generator\":\"brusher\"}");
+        write!(
+            json,
+            ",\"scene\":0,\"scenes\":[{{\"nodes\":{}}}]",
+            json_array(root_nodes)
+        )
+        .unwrap();
+        write!(json, ",\"nodes\":[{}]", self.nodes.join(",")).unwrap();
+        for (key, values) in [
+            ("meshes", &self.meshes),
+            ("materials", &self.materials),
+            ("accessors", &self.accessors),
+            ("bufferViews", &self.buffer_views),
+        ] {
+            if !values.is_empty() {
+                write!(json, ",\"{key}\":[{}]", values.join(",")).unwrap();
+            }
+        }
+        if !self.buffer.is_empty() {
+            let uri = buffer_uri
+                .map(|uri| format!(",\"uri\":{}", json_string(&uri)))
+                .unwrap_or_default();
+            write!(
+                json,
+                ",\"buffers\":[{{\"byteLength\":{}{uri}}}]",
+                self.buffer.len()
+            )
+            .unwrap();
+        }
+        json.push('}');
+        json
+    }
+
+    fn to_gltf(&self, root_nodes: &[usize]) -> String {
+        let uri = format!(
+            "data:application/octet-stream;base64,{}",
+            base64(&self.buffer)
+        );
+        self.to_json(root_nodes, Some(uri))
+    }
+
+    fn to_glb(&self, root_nodes: &[usize]) -> Vec<u8> {
+        let mut json = self.to_json(root_nodes, None).into_bytes();
+        while json.len() % 4 != 0 {
+            json.push(b' ');
+        }
+        let mut bin = self.buffer.clone();
+        while bin.len() % 4 != 0 {
+            bin.push(0);
+        }
+
+        let mut total_length = 12 + 8 + json.len();
+        if !bin.is_empty() {
+            total_length += 8 + bin.len();
+        }
+
+        let mut glb = Vec::with_capacity(total_length);
+        glb.extend_from_slice(b"glTF");
+        glb.extend_from_slice(&2u32.to_le_bytes());
+        glb.extend_from_slice(&(total_length as u32).to_le_bytes());
+        glb.extend_from_slice(&(json.len() as u32).to_le_bytes());
+        glb.extend_from_slice(b"JSON");
+        glb.extend_from_slice(&json);
+        if !bin.is_empty() {
+            glb.extend_from_slice(&(bin.len() as u32).to_le_bytes());
+            glb.extend_from_slice(b"BIN\0");
+            glb.extend_from_slice(&bin);
+        }
+        glb
+    }
+}
+
+/// The tangent of a polygon, following its U texture axis.
+///
+/// The W component gives the handedness of the bitangent against the V axis.
+fn tangent(polygon: &Polygon) -> [f32; 4] {
+    let normal = polygon.surface.normal;
+    let mapping = polygon.surface.texture_mapping();
+    let mut tangent = (mapping.u_axis - normal * normal.dot(mapping.u_axis)).normalize();
+    if !tangent.is_finite() {
+        tangent = normal.any_orthonormal_vector();
+    }
+    let handedness = if normal.cross(tangent).dot(mapping.v_axis) < 0.0 {
+        -1.0
+    } else {
+        1.0
+    };
+    [
+        tangent.x as f32,
+        tangent.y as f32,
+        tangent.z as f32,
+        handedness,
+    ]
+}
+
+fn bits(values: &[f32]) -> Vec<u32> {
+    values.iter().map(|value| value.to_bits()).collect()
+}
+
+fn json_string(value: &str) -> String {
+    let mut json = String::from("\"");
+    for c in value.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            c if (c as u32) < 0x20 => write!(json, "\\u{:04x}", c as u32).unwrap(),
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+    json
+}
+
+fn json_array<T: std::fmt::Display>(values: &[T]) -> String {
+    let values: Vec<String> = values.iter().map(|value| value.to_string()).collect();
+    format!("[{}]", values.join(","))
+}
+
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let b = [
+            chunk[0],
+            chunk.get(1).copied().unwrap_or(0),
+            chunk.get(2).copied().unwrap_or(0),
+        ];
+        let n = (b[0] as u32) << 16 | (b[1] as u32) << 8 | b[2] as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[(n >> (18 - 6 * i) & 63) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{brush::brushlet::tests::test_settings, prelude::*};
+
+    #[cfg(feature = "bevy")]
+    use bevy::math::DVec3;
+
+    #[cfg(not(feature = "bevy"))]
+    use glam::DVec3;
+
+    fn test_scene() -> BrusherScene {
+        let mut brush = Brush::new("Box");
+        brush.brushlets.push(Brushlet::from_cuboid(
+            Cuboid {
+                origin: DVec3::ZERO,
+                width: 2.0,
+                height: 2.0,
+                depth: 2.0,
+                material_indices: CuboidMaterialIndices {
+                    front: 0,
+                    back: 0,
+                    left: 0,
+                    right: 0,
+                    top: 1,
+                    bottom: 1,
+                },
+            },
+            test_settings(),
+        ));
+        let mut scene = BrusherScene::new();
+        scene.layers.push(Layer {
+            name: "Layer \"A\"".to_string(),
+            brushes: vec![brush, Brush::new("Empty")],
+            hidden: true,
+        });
+        scene
+    }
+
+    #[test]
+    fn test_base64() {
+        assert_eq!(base64(b"M"), "TQ==");
+        assert_eq!(base64(b"Ma"), "TWE=");
+        assert_eq!(base64(b"Man"), "TWFu");
+    }
+
+    #[test]
+    fn test_scene_to_gltf() {
+        let settings = GltfSettings {
+            material_names: &["grey", "green"],
+            tangents: true,
+        };
+        let json: serde_json::Value =
+            serde_json::from_str(&scene_to_gltf(&test_scene(), &settings)).unwrap();
+
+        assert_eq!(json["scenes"][0]["nodes"], serde_json::json!([2]));
+        assert_eq!(json["nodes"][2]["name"], "Layer \"A\"");
+        assert_eq!(json["nodes"][2]["children"], serde_json::json!([0, 1]));
+        assert_eq!(json["nodes"][2]["extras"]["hidden"], true);
+        assert_eq!(json["nodes"][0]["mesh"], 0);
+        assert!(json["nodes"][1].get("mesh").is_none());
+
+        let primitives = json["meshes"][0]["primitives"].as_array().unwrap();
+        assert_eq!(primitives.len(), 2);
+        let mut names: Vec<&str> = json["materials"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|material| material["name"].as_str().unwrap())
+            .collect();
+        names.sort();
+        assert_eq!(names, vec!["green", "grey"]);
+
+        let mut index_count = 0;
+        for primitive in primitives {
+            let attributes = &primitive["attributes"];
+            assert!(attributes.get("TANGENT").is_some());
+            let position = &json["accessors"][attributes["POSITION"].as_u64().unwrap() as usize];
+            assert_eq!(position["min"], serde_json::json!([-1, -1, -1]));
+            assert_eq!(position["max"], serde_json::json!([1, 1, 1]));
+            index_count += json["accessors"][primitive["indices"].as_u64().unwrap() as usize]
+                ["count"]
+                .as_u64()
+                .unwrap();
+        }
+        assert_eq!(index_count, 36);
+
+        let uri = json["buffers"][0]["uri"].as_str().unwrap();
+        assert!(uri.starts_with("data:application/octet-stream;base64,"));
+    }
+
+    #[test]
+    fn test_brush_to_glb() {
+        let brush = &test_scene().layers[0].brushes[0];
+        let glb = brush_to_glb(brush, &GltfSettings::default());
+
+        assert_eq!(&glb[0..4], b"glTF");
+        assert_eq!(u32::from_le_bytes(glb[4..8].try_into().unwrap()), 2);
+        assert_eq!(
+            u32::from_le_bytes(glb[8..12].try_into().unwrap()) as usize,
+            glb.len()
+        );
+
+        let json_length = u32::from_le_bytes(glb[12..16].try_into().unwrap()) as usize;
+        assert_eq!(&glb[16..20], b"JSON");
+        let json: serde_json::Value = serde_json::from_slice(&glb[20..20 + json_length]).unwrap();
+        assert_eq!(json["materials"][0]["name"], "material_0");
+        assert!(json["buffers"][0].get("uri").is_none());
+
+        let bin = &glb[20 + json_length..];
+        assert_eq!(&bin[4..8], b"BIN\0");
+        assert_eq!(
+            u32::from_le_bytes(bin[0..4].try_into().unwrap()) as u64,
+            json["buffers"][0]["byteLength"].as_u64().unwrap()
+        );
+    }
+}
@@ -0,0 +1,1166 @@
+//! Quake `.map` files, in both the standard and Valve 220 flavours.
+//!
+//! Maps are parsed into a [`MapFile`] which keeps every entity and its
+//! key/value pairs, and can then be turned into a [`BrusherScene`]. Every map
+//! brush becomes a [`Brush`] with a single plane defined [`Brushlet`].
+//! TrenchBroom layers become [`Layer`]s, everything else goes into the default layer.
+//!
+//! Scenes are written back as Valve 220 maps, with every brushlet emitted as
+//! its plane set. Only brushes made of unioned convex brushlets can be written.
+
+use std::{collections::HashMap, fmt};
+
+#[cfg(feature = "bevy")]
+use bevy::math::{DAffine3, DMat3, DVec2, DVec3};
+
+#[cfg(not(feature = "bevy"))]
+use glam::{DAffine3, DMat3, DVec2, DVec3};
+
+use super::{tokenize, Token};
+use crate::{
+    brush::{
+        brushlet::{Brushlet, BrushletSettings},
+        operations::Knife,
+        BooleanOp, Brush, MaterialIndex,
+    },
+    scene::{BrusherScene, Layer},
+    surface::{Surface, TextureMapping},
+};
+
+/// The name given to the layer holding worldspawn brushes.
+pub const DEFAULT_LAYER_NAME: &str = "Default Layer";
+
+/// An error encountered while parsing a `.map` file.
+#[derive(Debug, Clone, PartialEq)]
+pub enum MapError {
+    UnexpectedEndOfFile,
+    UnexpectedToken {
+        line: usize,
+        expected: &'static str,
+        found: String,
+    },
+    InvalidNumber {
+        line: usize,
+        token: String,
+    },
+}
+
+impl fmt::Display for MapError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MapError::UnexpectedEndOfFile => write!(f, "unexpected end of file"),
+            MapError::UnexpectedToken {
+                line,
+                expected,
+                found,
+            } => write!(f, "line {line}: expected {expected}, found `{found}`"),
+            MapError::InvalidNumber { line, token } => {
+                write!(f, "line {line}: `{token}` is not a valid number")
+            }
+        }
+    }
+}
+
+impl std::error::Error for MapError {}
+
+/// An error encountered while writing a scene to a `.map` file.
+#[derive(Debug, Clone, PartialEq)]
+pub enum MapExportError {
+    /// Map brushes are always unioned, so brushlets that subtract or
+    /// intersect cannot be represented.
+    UnsupportedOperation {
+        brush: String,
+        brushlet: String,
+        operation: BooleanOp,
+    },
+    /// The brushlet has no defining planes and its polygons are not convex.
+    NonConvexBrushlet { brush: String, brushlet: String },
+    /// Map brushes are always solid, so inverted brushlets cannot be represented.
+    InvertedBrushlet { brush: String, brushlet: String },
+    /// Entity keys and values are quoted without escapes, so they cannot contain `"`.
+    InvalidProperty { key: String, value: String },
+}
+
+impl fmt::Display for MapExportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MapExportError::UnsupportedOperation {
+                brush,
+                brushlet,
+                operation,
+            } => write!(
+                f,
+                "brushlet `{brushlet}` of brush `{brush}` uses {operation:?}, only unions can be exported"
+            ),
+            MapExportError::NonConvexBrushlet { brush, brushlet } => {
+                write!(f, "brushlet `{brushlet}` of brush `{brush}` is not convex")
+            }
+            MapExportError::InvertedBrushlet { brush, brushlet } => write!(
+                f,
+                "brushlet `{brushlet}` of brush `{brush}` is inverted, only solids can be exported"
+            ),
+            MapExportError::InvalidProperty { key, value } => write!(
+                f,
+                "property `{key}` with value `{value}` contains a `\"`, which map files cannot escape"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for MapExportError {}
+
+/// Settings for converting between map space and world space.
+///
+/// # Fields
+/// * `scale` - World units per map unit
+/// * `y_up` - Whether to convert from the Z up convention of Quake maps to Y up
+/// * `texture_size` - The texture size in texels that UVs are normalized against
+#[derive(Debug, Clone, Copy)]
+pub struct MapSettings {
+    pub scale: f64,
+    pub y_up: bool,
+    pub texture_size: DVec2,
+}
+
+impl Default for MapSettings {
+    fn default() -> Self {
+        Self {
+            scale: 1.0,
+            y_up: true,
+            texture_size: DVec2::ONE,
+        }
+    }
+}
+
+impl MapSettings {
+    /// The transform from map space to world space.
+    pub fn to_world(&self) -> DAffine3 {
+        let rotation = if self.y_up {
+            DMat3::from_cols(DVec3::X, -DVec3::Z, DVec3::Y)
+        } else {
+            DMat3::IDENTITY
+        };
+        DAffine3::from_mat3(rotation * self.scale)
+    }
+}
+
+/// The texture alignment of a map plane.
+///
+/// # Variants
+/// * `Standard` - Quake style alignment, the axes are derived from the plane normal
+/// * `Valve` - Valve 220 style alignment with explicit axes
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MapTextureAxes {
+    Standard {
+        offset: DVec2,
+        rotation: f64,
+        scale: DVec2,
+    },
+    Valve {
+        u_axis: DVec3,
+        v_axis: DVec3,
+        offset: DVec2,
+        rotation: f64,
+        scale: DVec2,
+    },
+}
+
+/// A plane of a map brush, defined by three points.
+///
+/// # Fields
+/// * `points` - Three points on the plane, clockwise when seen from the front
+/// * `texture` - The texture name
+/// * `axes` - The texture alignment
+#[derive(Debug, Clone, PartialEq)]
+pub struct MapPlane {
+    pub points: [DVec3; 3],
+    pub texture: String,
+    pub axes: MapTextureAxes,
+}
+
+impl MapPlane {
+    /// The outward facing surface of the plane in map space, or `None` if
+    /// the points are collinear.
+    pub fn to_surface(&self, material_idx: MaterialIndex, texture_size: DVec2) -> Option<Surface> {
+        let [a, b, c] = self.points;
+        let surface = Surface::from_points(b, a, c, material_idx);
+        if !surface.normal.is_finite() {
+            return None;
+        }
+        let texture = self.texture_mapping(surface.normal);
+        Some(surface.with_texture(TextureMapping {
+            offset: texture.offset / texture_size,
+            scale: texture.scale * texture_size,
+            ..texture
+        }))
+    }
+
+    /// Creates a Valve 220 plane from a surface in map space.
+    ///
+    /// `center` is projected onto the plane to anchor the three points.
+    pub fn from_surface(
+        surface: &Surface,
+        center: DVec3,
+        texture: String,
+        texture_size: DVec2,
+    ) -> Self {
+        const POINT_SPACING: f64 = 64.0;
+
+        let normal = surface.normal;
+        let origin = center - normal * (normal.dot(center) - surface.distance_from_origin);
+        let u = normal.any_orthonormal_vector();
+        let v = normal.cross(u);
+
+        let mapping = surface.texture_mapping();
+        Self {
+            points: [
+                origin + u * POINT_SPACING,
+                origin,
+                origin + v * POINT_SPACING,
+            ],
+            texture,
+            axes: MapTextureAxes::Valve {
+                u_axis: mapping.u_axis,
+                v_axis: mapping.v_axis,
+                offset: mapping.offset * texture_size,
+                rotation: 0.0,
+                scale: mapping.scale / texture_size,
+            },
+        }
+    }
+
+    /// The texture mapping of the plane in texels.
+    fn texture_mapping(&self, normal: DVec3) -> TextureMapping {
+        let (u_axis, v_axis, offset, scale) = match self.axes {
+            MapTextureAxes::Standard {
+                offset,
+                rotation,
+                scale,
+            } => {
+                let (u_axis, v_axis) = standard_texture_axes(normal, rotation);
+                (u_axis, v_axis, offset, scale)
+            }
+            MapTextureAxes::Valve {
+                u_axis,
+                v_axis,
+                offset,
+                scale,
+                ..
+            } => (u_axis, v_axis, offset, scale),
+        };
+        let non_zero = |value: f64| if value == 0.0 { 1.0 } else { value };
+        TextureMapping {
+            u_axis,
+            v_axis,
+            offset,
+            scale: DVec2::new(non_zero(scale.x), non_zero(scale.y)),
+        }
+    }
+}
+
+/// A convex map brush.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MapBrush {
+    pub planes: Vec<MapPlane>,
+}
+
+impl MapBrush {
+    /// Converts the brush to a plane defined brushlet in world space.
+    pub fn to_brushlet<F>(
+        &self,
+        name: &str,
+        settings: &MapSettings,
+        resolve_material: F,
+    ) -> Brushlet
+    where
+        F: FnMut(&str) -> MaterialIndex,
+    {
+        let mut resolve_material = resolve_material;
+        let to_world = settings.to_world();
+        let surfaces = self
+            .planes
+            .iter()
+            .filter_map(|plane| {
+                plane.to_surface(resolve_material(&plane.texture), settings.texture_size)
+            })
+            .map(|surface| surface.transform(to_world))
+            .collect();
+
+        Brushlet::from_surfaces(
+            surfaces,
+            BrushletSettings {
+                name: name.to_string(),
+                operation: BooleanOp::Union,
+                knives: Vec::new(),
+                inverted: false,
+            },
+        )
+    }
+}
+
+/// A map entity with its key/value pairs and brushes.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MapEntity {
+    pub properties: Vec<(String, String)>,
+    pub brushes: Vec<MapBrush>,
+}
+
+impl MapEntity {
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.properties
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn classname(&self) -> Option<&str> {
+        self.get("classname")
+    }
+
+    /// Whether the entity is a TrenchBroom layer.
+    pub fn is_layer(&self) -> bool {
+        self.classname() == Some("func_group") && self.get("_tb_type") == Some("_tb_layer")
+    }
+}
+
+/// A parsed `.map` file.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MapFile {
+    pub entities: Vec<MapEntity>,
+}
+
+impl MapFile {
+    pub fn parse(source: &str) -> Result<Self, MapError> {
+        let mut parser = Parser {
+            tokens: tokenize(source).ok_or(MapError::UnexpectedEndOfFile)?,
+            position: 0,
+        };
+        let mut entities = Vec::new();
+        while parser.peek().is_some() {
+            entities.push(parser.entity()?);
+        }
+        Ok(Self { entities })
+    }
+
+    pub fn worldspawn(&self) -> Option<&MapEntity> {
+        self.entities
+            .iter()
+            .find(|entity| entity.classname() == Some("worldspawn"))
+    }
+
+    /// Converts the brushes of the map into a scene.
+    ///
+    /// `resolve_material` maps texture names to material indices.
+    pub fn to_scene<F>(&self, settings: &MapSettings, resolve_material: F) -> BrusherScene
+    where
+        F: FnMut(&str) -> MaterialIndex,
+    {
+        let mut resolve_material = resolve_material;
+        let worldspawn = self.worldspawn();
+        let mut scene = BrusherScene::new();
+        scene.layers.push(Layer {
+            name: worldspawn
+                .and_then(|entity| entity.get("_tb_name"))
+                .unwrap_or(DEFAULT_LAYER_NAME)
+                .to_string(),
+            brushes: Vec::new(),
+            hidden: worldspawn.and_then(|entity| entity.get("_tb_layer_hidden")) == Some("1"),
+        });
+
+        let mut layer_ids = HashMap::new();
+        for entity in self.entities.iter().filter(|entity| entity.is_layer()) {
+            if let Some(id) = entity.get("_tb_id") {
+                layer_ids.insert(id, scene.layers.len());
+            }
+            scene.layers.push(Layer {
+                name: entity.get("_tb_name").unwrap_or("Unnamed").to_string(),
+                brushes: Vec::new(),
+                hidden: entity.get("_tb_layer_hidden") == Some("1"),
+            });
+        }
+
+        for entity in &self.entities {
+            let key = if entity.is_layer() {
+                "_tb_id"
+            } else {
+                "_tb_layer"
+            };
+            let layer_idx = entity
+                .get(key)
+                .and_then(|id| layer_ids.get(id))
+                .copied()
+                .unwrap_or(0);
+            let classname = entity.classname().unwrap_or("entity");
+
+            for map_brush in &entity.brushes {
+                let name = format!("{} {}", classname, scene.layers[layer_idx].brushes.len());
+                let mut brush = Brush::new(&name);
+                brush
+                    .brushlets
+                    .push(map_brush.to_brushlet(&name, settings, &mut resolve_material));
+                scene.layers[layer_idx].brushes.push(brush);
+            }
+        }
+
+        scene
+    }
+
+    /// Converts a scene into a Valve 220 map.
+    ///
+    /// The first layer becomes the worldspawn entity and every other layer a
+    /// TrenchBroom layer. The first layer's name and hidden flag are kept as
+    /// `_tb_name` and `_tb_layer_hidden` keys on the worldspawn, which editors
+    /// ignore. Brush and brushlet knives are written as extra planes.
+    /// `material_name` maps material indices to texture names.
+    pub fn from_scene<F>(
+        scene: &BrusherScene,
+        settings: &MapSettings,
+        material_name: F,
+    ) -> Result<Self, MapExportError>
+    where
+        F: FnMut(MaterialIndex) -> String,
+    {
+        let mut material_name = material_name;
+        let to_map = settings.to_world().inverse();
+        let mut entities = Vec::new();
+
+        for (layer_idx, layer) in scene.layers.iter().enumerate() {
+            let mut entity = MapEntity::default();
+            let mut property = |key: &str, value: String| {
+                entity.properties.push((key.to_string(), value));
+            };
+            if layer_idx == 0 {
+                property("classname", "worldspawn".to_string());
+                property("mapversion", "220".to_string());
+                if layer.name != DEFAULT_LAYER_NAME {
+                    property("_tb_name", layer.name.clone());
+                }
+            } else {
+                property("classname", "func_group".to_string());
+                property("_tb_type", "_tb_layer".to_string());
+                property("_tb_name", layer.name.clone());
+                property("_tb_id", layer_idx.to_string());
+            }
+            if layer.hidden {
+                property("_tb_layer_hidden", "1".to_string());
+            }
+            if let Some((key, value)) = entity
+                .properties
+                .iter()
+                .find(|(key, value)| key.contains('"') || value.contains('"'))
+            {
+                return Err(MapExportError::InvalidProperty {
+                    key: key.clone(),
+                    value: value.clone(),
+                });
+            }
+
+            for brush in &layer.brushes {
+                for (brushlet_idx, brushlet) in brush.brushlets.iter().enumerate() {
+                    if brushlet.settings.inverted {
+                        return Err(MapExportError::InvertedBrushlet {
+                            brush: brush.settings.name.clone(),
+                            brushlet: brushlet.settings.name.clone(),
+                        });
+                    }
+                    if brushlet_idx > 0 && brushlet.settings.operation != BooleanOp::Union {
+                        return Err(MapExportError::UnsupportedOperation {
+                            brush: brush.settings.name.clone(),
+                            brushlet: brushlet.settings.name.clone(),
+                            operation: brushlet.settings.operation,
+                        });
+                    }
+
+                    let surfaces =
+                        brushlet_surfaces(brushlet, &brush.settings.knives).ok_or_else(|| {
+                            MapExportError::NonConvexBrushlet {
+                                brush: brush.settings.name.clone(),
+                                brushlet: brushlet.settings.name.clone(),
+                            }
+                        })?;
+
+                    let center = to_map.transform_point3(brushlet.aabb.center());
+                    let planes = surfaces
+                        .iter()
+                        .map(|surface| {
+                            // Make the world aligned projection explicit so it survives the transform
+                            let surface = surface.with_texture(surface.texture_mapping());
+                            MapPlane::from_surface(
+                                &surface.transform(to_map),
+                                center,
+                                material_name(surface.material_idx),
+                                settings.texture_size,
+                            )
+                        })
+                        .collect();
+                    entity.brushes.push(MapBrush { planes });
+                }
+            }
+
+            entities.push(entity);
+        }
+
+        Ok(Self { entities })
+    }
+}
+
+/// Writes the map, brush planes keep the texture alignment format they were read with.
+impl fmt::Display for MapFile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (entity_idx, entity) in self.entities.iter().enumerate() {
+            writeln!(f, "// entity {entity_idx}")?;
+            writeln!(f, "{{")?;
+            for (key, value) in &entity.properties {
+                writeln!(f, "\"{key}\" \"{value}\"")?;
+            }
+            for (brush_idx, brush) in entity.brushes.iter().enumerate() {
+                writeln!(f, "// brush {brush_idx}")?;
+                writeln!(f, "{{")?;
+                for plane in &brush.planes {
+                    for point in plane.points {
+                        write!(f, "( {} ) ", format_vector(point))?;
+                    }
+                    write!(f, "{}", plane.texture)?;
+                    match plane.axes {
+                        MapTextureAxes::Standard {
+                            offset,
+                            rotation,
+                            scale,
+                        } => writeln!(
+                            f,
+                            " {} {} {} {} {}",
+                            format_number(offset.x),
+                            format_number(offset.y),
+                            format_number(rotation),
+                            format_number(scale.x),
+                            format_number(scale.y)
+                        )?,
+                        MapTextureAxes::Valve {
+                            u_axis,
+                            v_axis,
+                            offset,
+                            rotation,
+                            scale,
+                        } => writeln!(
+                            f,
+                            " [ {} {} ] [ {} {} ] {} {} {}",
+                            format_vector(u_axis),
+                            format_number(offset.x),
+                            format_vector(v_axis),
+                            format_number(offset.y),
+                            format_number(rotation),
+                            format_number(scale.x),
+                            format_number(scale.y)
+                        )?,
+                    }
+                }
+                writeln!(f, "}}")?;
+            }
+            writeln!(f, "}}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Parses a `.map` file and converts it into a scene.
+pub fn load_map<F>(
+    source: &str,
+    settings: &MapSettings,
+    resolve_material: F,
+) -> Result<BrusherScene, MapError>
+where
+    F: FnMut(&str) -> MaterialIndex,
+{
+    Ok(MapFile::parse(source)?.to_scene(settings, resolve_material))
+}
+
+/// Writes a scene as a Valve 220 `.map` file.
+pub fn save_map<F>(
+    scene: &BrusherScene,
+    settings: &MapSettings,
+    material_name: F,
+) -> Result<String, MapExportError>
+where
+    F: FnMut(MaterialIndex) -> String,
+{
+    Ok(MapFile::from_scene(scene, settings, material_name)?.to_string())
+}
+
+/// The planes bounding a brushlet, including the planes of its knives.
+///
+/// Brushlets without defining planes use the planes of their polygons,
+/// which is only valid if they are convex. Knives that are not a single
+/// plane make the result concave.
+pub(super) fn brushlet_surfaces(
+    brushlet: &Brushlet,
+    brush_knives: &[Knife],
+) -> Option<Vec<Surface>> {
+    let mut surfaces = match &brushlet.surfaces {
+        Some(surfaces) => surfaces.clone(),
+        None => {
+            let mut surfaces: Vec<Surface> = Vec::new();
+            for polygon in &brushlet.polygons {
+                if !surfaces.contains(&polygon.surface) {
+                    surfaces.push(polygon.surface);
+                }
+            }
+            let convex = brushlet.polygons.iter().all(|polygon| {
+                polygon.vertices.iter().all(|vertex| {
+                    surfaces.iter().all(|surface| {
+                        surface.normal.dot(vertex.pos)
+                            <= surface.distance_from_origin + Surface::EPSILON
+                    })
+                })
+            });
+            if !convex {
+                return None;
+            }
+            surfaces
+        }
+    };
+
+    for knife in brushlet.settings.knives.iter().chain(brush_knives) {
+        // Compound and bounded knives leave a concave solid behind
+        if !knife.is_plane() {
+            return None;
+        }
+        surfaces.extend(knife.planes());
+    }
+
+    Some(surfaces)
+}
+
+pub(super) fn format_number(value: f64) -> String {
+    let rounded = value.round();
+    let value = if (value - rounded).abs() < 1e-6 {
+        rounded
+    } else {
+        value
+    };
+    // Avoids writing negative zero
+    if value == 0.0 {
+        "0".to_string()
+    } else {
+        value.to_string()
+    }
+}
+
+pub(super) fn format_vector(vector: DVec3) -> String {
+    format!(
+        "{} {} {}",
+        format_number(vector.x),
+        format_number(vector.y),
+        format_number(vector.z)
+    )
+}
+
+/// Computes the texture axes of a standard format plane like Quake's `TextureAxisFromPlane`.
+fn standard_texture_axes(normal: DVec3, rotation: f64) -> (DVec3, DVec3) {
+    const BASE_AXES: [[DVec3; 3]; 6] = [
+        [DVec3::Z, DVec3::X, DVec3::NEG_Y],
+        [DVec3::NEG_Z, DVec3::X, DVec3::NEG_Y],
+        [DVec3::X, DVec3::Y, DVec3::NEG_Z],
+        [DVec3::NEG_X, DVec3::Y, DVec3::NEG_Z],
+        [DVec3::Y, DVec3::X, DVec3::NEG_Z],
+        [DVec3::NEG_Y, DVec3::X, DVec3::NEG_Z],
+    ];
+
+    let mut best = 0;
+    let mut best_dot = 0.0;
+    for (i, axes) in BASE_AXES.iter().enumerate() {
+        let dot = normal.dot(axes[0]);
+        if dot > best_dot {
+            best_dot = dot;
+            best = i;
+        }
+    }
+
+    // Exact values for the common right angles, like qbsp
+    let (sin, cos) = if rotation == 0.0 {
+        (0.0, 1.0)
+    } else if rotation == 90.0 {
+        (1.0, 0.0)
+    } else if rotation == 180.0 {
+        (0.0, -1.0)
+    } else if rotation == 270.0 {
+        (-1.0, 0.0)
+    } else {
+        rotation.to_radians().sin_cos()
+    };
+
+    let [_, u_axis, v_axis] = BASE_AXES[best];
+    let component = |axis: DVec3| {
+        if axis.x != 0.0 {
+            0
+        } else if axis.y != 0.0 {
+            1
+        } else {
+            2
+        }
+    };
+    let s = component(u_axis);
+    let t = component(v_axis);
+    let rotate = |axis: DVec3| {
+        let mut rotated = axis;
+        rotated[s] = cos * axis[s] - sin * axis[t];
+        rotated[t] = sin * axis[s] + cos * axis[t];
+        rotated
+    };
+
+    (rotate(u_axis), rotate(v_axis))
+}
+
+struct Parser<'a> {
+    tokens: Vec<Token<'a>>,
+    position: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<Token<'a>> {
+        self.tokens.get(self.position).copied()
+    }
+
+    fn next(&mut self) -> Result<Token<'a>, MapError> {
+        let token = self.peek().ok_or(MapError::UnexpectedEndOfFile)?;
+        self.position += 1;
+        Ok(token)
+    }
+
+    fn expect(&mut self, text: &'static str) -> Result<(), MapError> {
+        let token = self.next()?;
+        if !token.is(text) {
+            return Err(MapError::UnexpectedToken {
+                line: token.line,
+                expected: text,
+                found: token.text.to_string(),
+            });
+        }
+        Ok(())
+    }
+
+    fn number(&mut self) -> Result<f64, MapError> {
+        let token = self.next()?;
+        token.text.parse().map_err(|_| MapError::InvalidNumber {
+            line: token.line,
+            token: token.text.to_string(),
+        })
+    }
+
+    fn vector(&mut self) -> Result<DVec3, MapError> {
+        Ok(DVec3::new(self.number()?, self.number()?, self.number()?))
+    }
+
+    fn point(&mut self) -> Result<DVec3, MapError> {
+        self.expect("(")?;
+        let point = self.vector()?;
+        self.expect(")")?;
+        Ok(point)
+    }
+
+    fn entity(&mut self) -> Result<MapEntity, MapError> {
+        self.expect("{")?;
+        let mut entity = MapEntity::default();
+        loop {
+            let token = self.peek().ok_or(MapError::UnexpectedEndOfFile)?;
+            if token.is("}") {
+                self.position += 1;
+                return Ok(entity);
+            } else if token.is("{") {
+                entity.brushes.push(self.brush()?);
+            } else if token.quoted {
+                self.position += 1;
+                let value = self.next()?;
+                entity
+                    .properties
+                    .push((token.text.to_string(), value.text.to_string()));
+            } else {
+                return Err(MapError::UnexpectedToken {
+                    line: token.line,
+                    expected: "a key, a brush or `}`",
+                    found: token.text.to_string(),
+                });
+            }
+        }
+    }
+
+    fn brush(&mut self) -> Result<MapBrush, MapError> {
+        self.expect("{")?;
+        let mut planes = Vec::new();
+        loop {
+            let token = self.peek().ok_or(MapError::UnexpectedEndOfFile)?;
+            if token.is("}") {
+                self.position += 1;
+                return Ok(MapBrush { planes });
+            } else if token.is("(") {
+                planes.push(self.plane()?);
+            } else {
+                return Err(MapError::UnexpectedToken {
+                    line: token.line,
+                    expected: "a plane or `}`",
+                    found: token.text.to_string(),
+                });
+            }
+        }
+    }
+
+    fn plane(&mut self) -> Result<MapPlane, MapError> {
+        let points = [self.point()?, self.point()?, self.point()?];
+        let texture = self.next()?.text.to_string();
+
+        let axes = if self.peek().is_some_and(|token| token.is("[")) {
+            self.expect("[")?;
+            let u_axis = self.vector()?;
+            let u_offset = self.number()?;
+            self.expect("]")?;
+            self.expect("[")?;
+            let v_axis = self.vector()?;
+            let v_offset = self.number()?;
+            self.expect("]")?;
+            MapTextureAxes::Valve {
+                u_axis,
+                v_axis,
+                offset: DVec2::new(u_offset, v_offset),
+                rotation: self.number()?,
+                scale: DVec2::new(self.number()?, self.number()?),
+            }
+        } else {
+            MapTextureAxes::Standard {
+                offset: DVec2::new(self.number()?, self.number()?),
+                rotation: self.number()?,
+                scale: DVec2::new(self.number()?, self.number()?),
+            }
+        };
+
+        // Skip trailing surface flags written by Quake 2 style maps
+        while self
+            .peek()
+            .is_some_and(|token| !token.quoted && token.text.parse::<f64>().is_ok())
+        {
+            self.position += 1;
+        }
+
+        Ok(MapPlane {
+            points,
+            texture,
+            axes,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        brush::brushlet::tests::test_settings,
+        primitives::{Cuboid, CuboidMaterialIndices},
+    };
+
+    const STANDARD_MAP: &str = r#"// Game: Quake
+// Format: Standard
+// entity 0
+{
+"classname" "worldspawn"
+"wad" "quake.wad"
+// brush 0
+{
+( -64 -64 -16 ) ( -64 -63 -16 ) ( -64 -64 -15 ) wall 0 0 0 1 1
+( -64 -64 -16 ) ( -64 -64 -15 ) ( -63 -64 -16 ) wall 0 0 0 1 1
+( -64 -64 -16 ) ( -63 -64 -16 ) ( -64 -63 -16 ) floor 16 8 90 2 2
+( 64 64 16 ) ( 64 65 16 ) ( 65 64 16 ) floor 0 0 0 1 1
+( 64 64 16 ) ( 65 64 16 ) ( 64 64 17 ) wall 0 0 0 1 1
+( 64 64 16 ) ( 64 64 17 ) ( 64 65 16 ) wall 0 0 0 1 1
+}
+}
+// entity 1
+{
+"classname" "info_player_start"
+"origin" "0 0 40"
+}
+"#;
+
+    const VALVE_MAP: &str = r#"// Game: Half-Life
+// Format: Valve
+{
+"classname" "worldspawn"
+"mapversion" "220"
+{
+( 0 0 0 ) ( 0 1 0 ) ( 0 0 1 ) {fence [ 0 1 0 0 ] [ 0 0 -1 0 ] 0 1 1
+( 0 0 0 ) ( 0 0 1 ) ( 1 0 0 ) {fence [ 1 0 0 0 ] [ 0 0 -1 0 ] 0 1 1
+( 0 0 0 ) ( 1 0 0 ) ( 0 1 0 ) {fence [ 1 0 0 0 ] [ 0 -1 0 0 ] 0 1 1
+( 32 32 32 ) ( 32 33 32 ) ( 33 32 32 ) {fence [ 1 0 0 0 ] [ 0 -1 0 0 ] 0 1 1
+( 32 32 32 ) ( 33 32 32 ) ( 32 32 33 ) {fence [ 1 0 0 0 ] [ 0 0 -1 0 ] 0 1 1
+( 32 32 32 ) ( 32 32 33 ) ( 32 33 32 ) {fence [ 0 1 0 0 ] [ 0 0 -1 0 ] 0 1 1
+}
+}
+{
+"classname" "func_group"
+"_tb_type" "_tb_layer"
+"_tb_name" "Details"
+"_tb_id" "2"
+"_tb_layer_hidden" "1"
+{
+( 64 0 0 ) ( 64 1 0 ) ( 64 0 1 ) *water [ 0 1 0 8 ] [ 0 0 -1 4 ] 0 0.5 0.5
+( 64 0 0 ) ( 64 0 1 ) ( 65 0 0 ) *water [ 1 0 0 8 ] [ 0 0 -1 4 ] 0 0.5 0.5
+( 64 0 0 ) ( 65 0 0 ) ( 64 1 0 ) *water [ 1 0 0 8 ] [ 0 -1 0 4 ] 0 0.5 0.5
+( 96 32 32 ) ( 96 33 32 ) ( 97 32 32 ) *water [ 1 0 0 8 ] [ 0 -1 0 4 ] 0 0.5 0.5
+( 96 32 32 ) ( 97 32 32 ) ( 96 32 33 ) *water [ 1 0 0 8 ] [ 0 0 -1 4 ] 0 0.5 0.5
+( 96 32 32 ) ( 96 32 33 ) ( 96 33 32 ) *water [ 0 1 0 8 ] [ 0 0 -1 4 ] 0 0.5 0.5
+}
+}
+"#;
+
+    fn materials(name: &str) -> MaterialIndex {
+        match name {
+            "floor" => 1,
+            "wall" => 2,
+            _ => 3,
+        }
+    }
+
+    #[test]
+    fn test_parse_standard_map() {
+        let map = MapFile::parse(STANDARD_MAP).unwrap();
+        assert_eq!(map.entities.len(), 2);
+        assert_eq!(map.worldspawn().unwrap().get("wad"), Some("quake.wad"));
+        assert_eq!(map.entities[1].get("origin"), Some("0 0 40"));
+
+        let plane = &map.entities[0].brushes[0].planes[2];
+        assert_eq!(plane.texture, "floor");
+        assert_eq!(
+            plane.axes,
+            MapTextureAxes::Standard {
+                offset: DVec2::new(16.0, 8.0),
+                rotation: 90.0,
+                scale: DVec2::new(2.0, 2.0),
+            }
+        );
+    }
+
+    #[test]
+    fn test_standard_map_to_scene() {
+        let scene = load_map(STANDARD_MAP, &MapSettings::default(), materials).unwrap();
+        assert_eq!(scene.layers.len(), 1);
+        assert_eq!(scene.layers[0].brushes.len(), 1);
+
+        let brushlet = &scene.layers[0].brushes[0].brushlets[0];
+        assert_eq!(brushlet.polygons.len(), 6);
+        assert!((brushlet.aabb.min - DVec3::new(-64.0, -16.0, -64.0)).length() < 1e-9);
+        assert!((brushlet.aabb.max - DVec3::new(64.0, 16.0, 64.0)).length() < 1e-9);
+
+        for polygon in &brushlet.polygons {
+            let expected = if polygon.surface.normal.y.abs() > 0.5 {
+                1
+            } else {
+                2
+            };
+            assert_eq!(polygon.surface.material_idx, expected);
+        }
+
+        // The floor is rotated by 90 degrees, scaled by 2 and offset by (16, 8)
+        let floor = brushlet
+            .polygons
+            .iter()
+            .find(|polygon| polygon.surface.normal.y < -0.5)
+            .unwrap();
+        let uv = floor.surface.compute_uv(DVec3::new(10.0, -16.0, -4.0));
+        assert!((uv - DVec2::new(4.0 / 2.0 + 16.0, 10.0 / 2.0 + 8.0)).length() < 1e-9);
+    }
+
+    #[test]
+    fn test_valve_map_layers() {
+        let mut names = Vec::new();
+        let settings = MapSettings {
+            scale: 1.0 / 32.0,
+            y_up: false,
+            texture_size: DVec2::new(64.0, 64.0),
+        };
+        let scene = load_map(VALVE_MAP, &settings, |name| {
+            names.push(name.to_string());
+            3
+        })
+        .unwrap();
+
+        assert!(names.iter().any(|name| name == "{fence"));
+        assert!(names.iter().any(|name| name == "*water"));
+        assert_eq!(scene.layers.len(), 2);
+        assert_eq!(scene.layers[1].name, "Details");
+        assert!(scene.layers[1].hidden);
+
+        let brushlet = &scene.layers[1].brushes[0].brushlets[0];
+        assert!((brushlet.aabb.min - DVec3::new(2.0, 0.0, 0.0)).length() < 1e-9);
+        assert!((brushlet.aabb.max - DVec3::new(3.0, 1.0, 1.0)).length() < 1e-9);
+
+        // 0.5 map units per texel on a 64 texel texture, offset by 8 texels
+        let top = brushlet
+            .polygons
+            .iter()
+            .find(|polygon| polygon.surface.normal.z > 0.5)
+            .unwrap();
+        let uv = top.surface.compute_uv(DVec3::new(2.5, 0.5, 1.0));
+        let expected = DVec2::new((80.0 / 0.5 + 8.0) / 64.0, (-16.0 / 0.5 + 4.0) / 64.0);
+        assert!((uv - expected).length() < 1e-9);
+    }
+
+    fn test_brushlet(origin: DVec3, operation: BooleanOp, knives: Vec<Knife>) -> Brushlet {
+        Brushlet::from_cuboid(
+            Cuboid {
+                origin,
+                width: 2.0,
+                height: 1.0,
+                depth: 4.0,
+                material_indices: CuboidMaterialIndices {
+                    front: 1,
+                    back: 2,
+                    left: 3,
+                    right: 4,
+                    top: 5,
+                    bottom: 6,
+                },
+            },
+            BrushletSettings {
+                operation,
+                knives,
+                ..test_settings()
+            },
+        )
+    }
+
+    #[test]
+    fn test_export_round_trip() {
+        let mut brush = Brush::new("Cut");
+        brush.brushlets.push(test_brushlet(
+            DVec3::ZERO,
+            BooleanOp::Union,
+            vec![Knife::new(DVec3::new(1.0, 1.0, 0.0), 1.0, 7)],
+        ));
+        let mut scene = BrusherScene::new();
+        scene.layers.push(Layer {
+            name: "World".to_string(),
+            brushes: vec![brush],
+            hidden: true,
+        });
+        let mut detail = Brush::new("Detail");
+        detail.brushlets.push(test_brushlet(
+            DVec3::new(10.0, 0.0, 0.0),
+            BooleanOp::Subtract,
+            Vec::new(),
+        ));
+        detail.brushlets.push(test_brushlet(
+            DVec3::new(10.0, 1.0, 0.0),
+            BooleanOp::Union,
+            Vec::new(),
+        ));
+        scene.layers.push(Layer {
+            name: "Details { east } // 2".to_string(),
+            brushes: vec![detail],
+            hidden: true,
+        });
+
+        let settings = MapSettings {
+            scale: 1.0 / 16.0,
+            y_up: true,
+            texture_size: DVec2::new(64.0, 32.0),
+        };
+        let source = save_map(&scene, &settings, |idx| format!("mat{idx}")).unwrap();
+        let loaded = load_map(&source, &settings, |name| name[3..].parse().unwrap()).unwrap();
+
+        assert_eq!(loaded.layers.len(), 2);
+        assert_eq!(loaded.layers[0].name, "World");
+        assert!(loaded.layers[0].hidden);
+        assert_eq!(loaded.layers[1].name, "Details { east } // 2");
+        assert!(loaded.layers[1].hidden);
+        assert_eq!(loaded.layers[1].brushes.len(), 2);
+
+        let original = &scene.layers[0].brushes[0].brushlets[0];
+        let cut = &loaded.layers[0].brushes[0].brushlets[0];
+        assert_eq!(cut.polygons.len(), 7);
+        assert!((cut.aabb.min - original.aabb.min).length() < 1e-9);
+        assert!((cut.aabb.max - DVec3::new(1.0, 0.5, 2.0)).length() < 1e-9);
+
+        let mut materials: Vec<usize> = cut
+            .polygons
+            .iter()
+            .map(|polygon| polygon.surface.material_idx)
+            .collect();
+        materials.sort();
+        assert_eq!(materials, vec![1, 2, 3, 4, 5, 6, 7]);
+
+        for polygon in &original.polygons {
+            let loaded_polygon = cut
+                .polygons
+                .iter()
+                .find(|other| other.surface == polygon.surface)
+                .unwrap();
+            let point = polygon.vertices[0].pos;
+            let uv = loaded_polygon.surface.compute_uv(point);
+            assert!((uv - polygon.surface.compute_uv(point)).length() < 1e-9);
+        }
+
+        // Quotes cannot be escaped in a map file
+        scene.layers[1].name = "Say \"cheese\"".to_string();
+        assert_eq!(
+            save_map(&scene, &settings, |idx| format!("mat{idx}")).unwrap_err(),
+            MapExportError::InvalidProperty {
+                key: "_tb_name".to_string(),
+                value: "Say \"cheese\"".to_string(),
+            }
+        );
+    }
+
+    #[test]
+    fn test_export_unsupported_operation() {
+        let mut brush = Brush::new("Room");
+        brush
+            .brushlets
+            .push(test_brushlet(DVec3::ZERO, BooleanOp::Union, Vec::new()));
+        brush
+            .brushlets
+            .push(test_brushlet(DVec3::X, BooleanOp::Subtract, Vec::new()));
+        let mut scene = BrusherScene::new();
+        scene.layers.push(Layer {
+            name: DEFAULT_LAYER_NAME.to_string(),
+            brushes: vec![brush],
+            hidden: false,
+        });
+
+        let error = save_map(&scene, &MapSettings::default(), |_| "wall".to_string()).unwrap_err();
+        assert_eq!(
+            error,
+            MapExportError::UnsupportedOperation {
+                brush: "Room".to_string(),
+                brushlet: "Test".to_string(),
+                operation: BooleanOp::Subtract,
+            }
+        );
+
+        let brush = &mut scene.layers[0].brushes[0];
+        brush.brushlets.pop();
+        brush.brushlets[0].settings.inverted = true;
+        let error = save_map(&scene, &MapSettings::default(), |_| "wall".to_string()).unwrap_err();
+        assert_eq!(
+            error,
+            MapExportError::InvertedBrushlet {
+                brush: "Room".to_string(),
+                brushlet: "Test".to_string(),
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_error() {
+        let error =
+            MapFile::parse("{\n\"classname\" \"worldspawn\"\n{\npatchDef2\n}\n}").unwrap_err();
+        assert_eq!(
+            error,
+            MapError::UnexpectedToken {
+                line: 4,
+                expected: "a plane or `}`",
+                found: "patchDef2".to_string(),
+            }
+        );
+        assert_eq!(
+            MapFile::parse("{ \"classname\" ").unwrap_err(),
+            MapError::UnexpectedEndOfFile
+        );
+    }
+}
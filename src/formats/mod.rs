@@ -0,0 +1,67 @@
+//! Readers and writers for level editor and interchange file formats.
+
+pub mod gltf;
+pub mod map;
+pub mod obj;
+pub mod vmf;
+
+#[derive(Debug, Clone, Copy)]
+struct Token<'a> {
+    text: &'a str,
+    quoted: bool,
+    line: usize,
+}
+
+impl Token<'_> {
+    fn is(&self, text: &str) -> bool {
+        !self.quoted && self.text == text
+    }
+}
+
+/// Splits the source into whitespace separated tokens, honouring quoted
+/// strings and `//` comments. Returns `None` for an unterminated string.
+fn tokenize(source: &str) -> Option<Vec<Token<'_>>> {
+    let mut tokens = Vec::new();
+    let mut line = 1;
+    let bytes = source.as_bytes();
+    let mut i = 0;
+
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\n' => {
+                line += 1;
+                i += 1;
+            }
+            c if c.is_ascii_whitespace() => i += 1,
+            b'/' if bytes.get(i + 1) == Some(&b'/') => {
+                while i < bytes.len() && bytes[i] != b'\n' {
+                    i += 1;
+                }
+            }
+            b'"' => {
+                let start = i + 1;
+                let end = source[start..].find('"').map(|offset| start + offset)?;
+                tokens.push(Token {
+                    text: &source[start..end],
+                    quoted: true,
+                    line,
+                });
+                line += source[start..end].matches('\n').count();
+                i = end + 1;
+            }
+            _ => {
+                let start = i;
+                while i < bytes.len() && !bytes[i].is_ascii_whitespace() {
+                    i += 1;
+                }
+                tokens.push(Token {
+                    text: &source[start..i],
+                    quoted: false,
+                    line,
+                });
+            }
+        }
+    }
+
+    Some(tokens)
+}
@@ -0,0 +1,253 @@
+//! Wavefront `.obj` and `.mtl` files.
+//!
+//! Polygons are written as n-gon faces grouped into one `usemtl` section per
+//! material index. Positions, UVs and normals are deduplicated so neighbouring
+//! faces share vertices. UVs come from [`Surface::compute_uv`](crate::surface::Surface::compute_uv)
+//! with V flipped, since OBJ places the UV origin at the bottom left.
+//! Polygons smoothed with [`MeshData::smooth_normals`] are written in `s`
+//! smoothing groups, flat ones with smoothing off.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::Write,
+};
+
+use crate::brush::{MaterialIndex, MeshData};
+
+/// The contents of an `.obj` file and its material library.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ObjFile {
+    pub obj: String,
+    pub mtl: String,
+}
+
+/// Writes mesh data as an `.obj` file referencing the material library `mtl_file_name`.
+///
+/// `material_name` maps material indices to material names.
+pub fn save_obj<F>(mesh_data: &MeshData, mtl_file_name: &str, material_name: F) -> ObjFile
+where
+    F: FnMut(MaterialIndex) -> String,
+{
+    let mut material_name = material_name;
+
+    let mut groups: BTreeMap<MaterialIndex, Vec<usize>> = BTreeMap::new();
+    for (idx, polygon) in mesh_data.polygons.iter().enumerate() {
+        groups
+            .entry(polygon.surface.material_idx)
+            .or_default()
+            .push(idx);
+    }
+
+    let smoothing_groups = smoothing_groups(mesh_data);
+    let mut smoothing_group = 0;
+
+    let mut positions = Attribute::new("v");
+    let mut uvs = Attribute::new("vt");
+    let mut normals = Attribute::new("vn");
+    let mut faces = String::new();
+    let mut mtl = String::new();
+
+    for (material_idx, polygons) in groups {
+        let name = material_name(material_idx).replace(char::is_whitespace, "_");
+        writeln!(faces, "usemtl {name}").unwrap();
+        writeln!(mtl, "newmtl {name}\nKd 0.8 0.8 0.8\n").unwrap();
+
+        for idx in polygons {
+            let polygon = &mesh_data.polygons[idx];
+            if smoothing_groups[idx] != smoothing_group {
+                smoothing_group = smoothing_groups[idx];
+                match smoothing_group {
+                    0 => writeln!(faces, "s off").unwrap(),
+                    group => writeln!(faces, "s {group}").unwrap(),
+                }
+            }
+            faces.push('f');
+            for vertex in &polygon.vertices {
+                let pos = vertex.pos;
+                let uv = polygon.surface.compute_uv(pos);
+                let normal = vertex.normal;
+                write!(
+                    faces,
+                    " {}/{}/{}",
+                    positions.index(format!(
+                        "{} {} {}",
+                        pos.x as f32, pos.y as f32, pos.z as f32
+                    )),
+                    uvs.index(format!("{} {}", uv.x as f32, 1.0 - uv.y as f32)),
+                    normals.index(format!(
+                        "{} {} {}",
+                        normal.x as f32, normal.y as f32, normal.z as f32
+                    ))
+                )
+                .unwrap();
+            }
+            faces.push('\n');
+        }
+    }
+
+    let mut obj = format!("# brusher\nmtllib {mtl_file_name}\n");
+    obj.push_str(&positions.lines);
+    obj.push_str(&uvs.lines);
+    obj.push_str(&normals.lines);
+    obj.push_str(&faces);
+
+    ObjFile { obj, mtl }
+}
+
+/// The smoothing group of every polygon, 0 for polygons with flat normals.
+///
+/// Polygons with a vertex normal differing from their face normal were
+/// smoothed. Smoothed polygons sharing a vertex, with the same position and
+/// normal, are in the same group.
+fn smoothing_groups(mesh_data: &MeshData) -> Vec<usize> {
+    let mut parents: Vec<usize> = (0..mesh_data.polygons.len()).collect();
+    let mut smoothed = vec![false; mesh_data.polygons.len()];
+    let mut shared_vertices: HashMap<[u32; 6], usize> = HashMap::new();
+    for (idx, polygon) in mesh_data.polygons.iter().enumerate() {
+        smoothed[idx] = polygon
+            .vertices
+            .iter()
+            .any(|vertex| !vertex.normal.abs_diff_eq(polygon.surface.normal, 1e-6));
+        if !smoothed[idx] {
+            continue;
+        }
+        for vertex in &polygon.vertices {
+            let (pos, normal) = (vertex.pos, vertex.normal);
+            let key = [pos.x, pos.y, pos.z, normal.x, normal.y, normal.z]
+                .map(|value| (value as f32).to_bits());
+            let other = *shared_vertices.entry(key).or_insert(idx);
+            let (a, b) = (root(&mut parents, idx), root(&mut parents, other));
+            parents[a] = b;
+        }
+    }
+
+    // Number the groups in the order they first appear
+    let mut numbers = HashMap::new();
+    (0..mesh_data.polygons.len())
+        .map(|idx| {
+            if !smoothed[idx] {
+                return 0;
+            }
+            let group = root(&mut parents, idx);
+            let next = numbers.len() + 1;
+            *numbers.entry(group).or_insert(next)
+        })
+        .collect()
+}
+
+/// The representative of the set containing `idx`, for a union-find over `parents`.
+fn root(parents: &mut [usize], mut idx: usize) -> usize {
+    while parents[idx] != idx {
+        parents[idx] = parents[parents[idx]];
+        idx = parents[idx];
+    }
+    idx
+}
+
+/// A deduplicated list of vertex attribute lines.
+struct Attribute {
+    keyword: &'static str,
+    lines: String,
+    indices: HashMap<String, usize>,
+}
+
+impl Attribute {
+    fn new(keyword: &'static str) -> Self {
+        Self {
+            keyword,
+            lines: String::new(),
+            indices: HashMap::new(),
+        }
+    }
+
+    /// Returns the one based index of the value, adding it if it is new.
+    fn index(&mut self, value: String) -> usize {
+        let next = self.indices.len() + 1;
+        *self.indices.entry(value).or_insert_with_key(|value| {
+            writeln!(self.lines, "{} {}", self.keyword, value).unwrap();
+            next
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{brush::brushlet::tests::test_settings, prelude::*};
+
+    #[test]
+    fn test_save_obj() {
+        let brushlet = Brushlet::from_cuboid(
+            Cuboid {
+                origin: DVec3::ZERO,
+                width: 1.0,
+                height: 1.0,
+                depth: 1.0,
+                material_indices: CuboidMaterialIndices {
+                    front: 0,
+                    back: 0,
+                    left: 0,
+                    right: 0,
+                    top: 1,
+                    bottom: 1,
+                },
+            },
+            test_settings(),
+        );
+
+        let file = save_obj(&brushlet.to_mesh_data(), "test.mtl", |idx| match idx {
+            0 => "proto grey".to_string(),
+            _ => "proto_green".to_string(),
+        });
+
+        let count = |prefix: &str| file.obj.lines().filter(|l| l.starts_with(prefix)).count();
+        assert_eq!(count("v "), 8);
+        assert_eq!(count("vn "), 6);
+        assert_eq!(count("f "), 6);
+        assert_eq!(count("usemtl "), 2);
+        assert!(file.obj.contains("mtllib test.mtl"));
+        assert!(file.obj.contains("usemtl proto_grey\nf"));
+        assert!(file.mtl.contains("newmtl proto_grey"));
+        assert!(file.mtl.contains("newmtl proto_green"));
+
+        let top_faces = file.obj.split("usemtl proto_green\n").nth(1).unwrap();
+        assert_eq!(top_faces.lines().count(), 2);
+        for face in file.obj.lines().filter(|l| l.starts_with("f ")) {
+            assert_eq!(face.split_whitespace().count(), 5);
+        }
+        assert_eq!(count("s "), 0);
+
+        // The sides of a smoothed cylinder form one group, the caps stay flat
+        let cylinder = Brushlet::from_cylinder(
+            Cylinder {
+                origin: DVec3::ZERO,
+                radius: 1.0,
+                height: 2.0,
+                segments: 8,
+                axis: DVec3::Y,
+                material_indices: CylinderMaterialIndices {
+                    sides: 0,
+                    top: 1,
+                    bottom: 1,
+                },
+            },
+            brushlet.settings.clone(),
+        );
+        let mut mesh_data = cylinder.to_mesh_data();
+        mesh_data.smooth_normals(SmoothNormalsSettings::default());
+        let file = save_obj(&mesh_data, "test.mtl", |idx| format!("mat{idx}"));
+        let statements: Vec<&str> = file
+            .obj
+            .lines()
+            .filter(|l| l.starts_with("usemtl ") || l.starts_with("s "))
+            .collect();
+        assert_eq!(statements, ["usemtl mat0", "s 1", "usemtl mat1", "s off"]);
+        let faces: Vec<usize> = file
+            .obj
+            .split("usemtl ")
+            .skip(1)
+            .map(|section| section.lines().filter(|l| l.starts_with("f ")).count())
+            .collect();
+        assert_eq!(faces, [8, 2]);
+    }
+}
@@ -0,0 +1,708 @@
+//! Valve Hammer `.vmf` files.
+//!
+//! VMF files are KeyValues trees. Every `solid` of the `world` and of brush
+//! entities becomes a [`Brush`] with a single plane defined brushlet, and
+//! `visgroup`s become [`Layer`]s. Solids outside any visgroup go into the
+//! default layer, and solids hidden in the editor go into a hidden layer of
+//! their own. The same [`MapSettings`] as `.map` files are used to convert
+//! between Hammer space and world space.
+
+use std::{collections::HashMap, fmt};
+
+#[cfg(feature = "bevy")]
+use bevy::math::{DVec2, DVec3};
+
+#[cfg(not(feature = "bevy"))]
+use glam::{DVec2, DVec3};
+
+use super::{
+    map::{
+        brushlet_surfaces, format_number, format_vector, MapBrush, MapExportError, MapPlane,
+        MapSettings, MapTextureAxes, DEFAULT_LAYER_NAME,
+    },
+    tokenize,
+};
+use crate::{
+    brush::{BooleanOp, Brush, MaterialIndex},
+    scene::{BrusherScene, Layer},
+};
+
+/// The name given to the layer holding solids from `hidden` blocks.
+pub const HIDDEN_LAYER_NAME: &str = "Hidden";
+
+/// An error encountered while parsing a `.vmf` file.
+#[derive(Debug, Clone, PartialEq)]
+pub enum VmfError {
+    UnexpectedEndOfFile,
+    UnexpectedToken {
+        line: usize,
+        expected: &'static str,
+        found: String,
+    },
+    InvalidValue {
+        key: String,
+        value: String,
+    },
+}
+
+impl fmt::Display for VmfError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VmfError::UnexpectedEndOfFile => write!(f, "unexpected end of file"),
+            VmfError::UnexpectedToken {
+                line,
+                expected,
+                found,
+            } => write!(f, "line {line}: expected {expected}, found `{found}`"),
+            VmfError::InvalidValue { key, value } => {
+                write!(f, "`{value}` is not a valid value for `{key}`")
+            }
+        }
+    }
+}
+
+impl std::error::Error for VmfError {}
+
+/// A KeyValues block.
+///
+/// # Fields
+/// * `name` - The name of the block, such as `solid` or `side`
+/// * `properties` - The key/value pairs of the block, in file order
+/// * `children` - The nested blocks, in file order
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct KeyValues {
+    pub name: String,
+    pub properties: Vec<(String, String)>,
+    pub children: Vec<KeyValues>,
+}
+
+impl KeyValues {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            ..Default::default()
+        }
+    }
+
+    /// Parses all top level blocks of a KeyValues file.
+    pub fn parse(source: &str) -> Result<Vec<Self>, VmfError> {
+        let tokens = tokenize(source).ok_or(VmfError::UnexpectedEndOfFile)?;
+        let mut stack = vec![KeyValues::default()];
+        let mut i = 0;
+
+        while i < tokens.len() {
+            let token = tokens[i];
+            if token.is("}") {
+                if stack.len() == 1 {
+                    return Err(VmfError::UnexpectedToken {
+                        line: token.line,
+                        expected: "a key or a block",
+                        found: token.text.to_string(),
+                    });
+                }
+                let block = stack.pop().unwrap();
+                stack.last_mut().unwrap().children.push(block);
+                i += 1;
+                continue;
+            }
+
+            let next = tokens.get(i + 1).ok_or(VmfError::UnexpectedEndOfFile)?;
+            if next.is("{") {
+                stack.push(KeyValues::new(token.text));
+            } else if next.is("}") {
+                return Err(VmfError::UnexpectedToken {
+                    line: next.line,
+                    expected: "a value",
+                    found: next.text.to_string(),
+                });
+            } else {
+                stack
+                    .last_mut()
+                    .unwrap()
+                    .properties
+                    .push((token.text.to_string(), next.text.to_string()));
+            }
+            i += 2;
+        }
+
+        if stack.len() > 1 {
+            return Err(VmfError::UnexpectedEndOfFile);
+        }
+        Ok(stack.pop().unwrap().children)
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.properties
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn set(&mut self, key: &str, value: impl Into<String>) {
+        self.properties.push((key.to_string(), value.into()));
+    }
+
+    pub fn child(&self, name: &str) -> Option<&KeyValues> {
+        self.children.iter().find(|child| child.name == name)
+    }
+
+    pub fn children_named<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a KeyValues> {
+        self.children.iter().filter(move |child| child.name == name)
+    }
+
+    fn write(&self, f: &mut fmt::Formatter<'_>, depth: usize) -> fmt::Result {
+        let indent = "\t".repeat(depth);
+        writeln!(f, "{indent}{}", self.name)?;
+        writeln!(f, "{indent}{{")?;
+        for (key, value) in &self.properties {
+            writeln!(f, "{indent}\t\"{key}\" \"{value}\"")?;
+        }
+        for child in &self.children {
+            child.write(f, depth + 1)?;
+        }
+        writeln!(f, "{indent}}}")
+    }
+}
+
+impl fmt::Display for KeyValues {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.write(f, 0)
+    }
+}
+
+/// Parses a `.vmf` file and converts its solids into a scene.
+///
+/// `resolve_material` maps material names to material indices.
+pub fn load_vmf<F>(
+    source: &str,
+    settings: &MapSettings,
+    resolve_material: F,
+) -> Result<BrusherScene, VmfError>
+where
+    F: FnMut(&str) -> MaterialIndex,
+{
+    let mut resolve_material = resolve_material;
+    let blocks = KeyValues::parse(source)?;
+
+    let mut scene = BrusherScene::new();
+    scene.layers.push(Layer {
+        name: DEFAULT_LAYER_NAME.to_string(),
+        brushes: Vec::new(),
+        hidden: false,
+    });
+
+    let mut layer_ids = HashMap::new();
+    for visgroups in blocks.iter().filter(|block| block.name == "visgroups") {
+        add_visgroup_layers(visgroups, &mut scene, &mut layer_ids);
+    }
+    // A layer is hidden only if every solid in it is hidden
+    let mut layer_shown = vec![false; scene.layers.len()];
+    let mut hidden_layer = None;
+
+    for block in &blocks {
+        if block.name != "world" && block.name != "entity" {
+            continue;
+        }
+        let classname = block.get("classname").unwrap_or(&block.name);
+
+        // Solids hidden in the editor are wrapped in `hidden` blocks
+        let solids = block.children_named("solid").map(|solid| (solid, false));
+        let hidden_solids = block
+            .children_named("hidden")
+            .flat_map(|hidden| hidden.children_named("solid"))
+            .map(|solid| (solid, true));
+
+        for (solid, hidden) in solids.chain(hidden_solids) {
+            let editor = solid.child("editor");
+            let layer_idx = if hidden {
+                *hidden_layer.get_or_insert_with(|| {
+                    scene.layers.push(Layer {
+                        name: HIDDEN_LAYER_NAME.to_string(),
+                        brushes: Vec::new(),
+                        hidden: true,
+                    });
+                    layer_shown.push(false);
+                    scene.layers.len() - 1
+                })
+            } else {
+                editor
+                    .and_then(|editor| editor.get("visgroupid"))
+                    .and_then(|id| layer_ids.get(id))
+                    .copied()
+                    .unwrap_or(0)
+            };
+            layer_shown[layer_idx] |=
+                !hidden && editor.and_then(|editor| editor.get("visgroupshown")) != Some("0");
+
+            let planes = solid
+                .children_named("side")
+                .map(parse_side)
+                .collect::<Result<Vec<_>, _>>()?;
+            let name = format!("{} {}", classname, solid.get("id").unwrap_or("0"));
+            let mut brush = Brush::new(&name);
+            brush.brushlets.push(MapBrush { planes }.to_brushlet(
+                &name,
+                settings,
+                &mut resolve_material,
+            ));
+            scene.layers[layer_idx].brushes.push(brush);
+        }
+    }
+
+    for (layer, shown) in scene.layers.iter_mut().zip(layer_shown).skip(1) {
+        layer.hidden = !layer.brushes.is_empty() && !shown;
+    }
+
+    Ok(scene)
+}
+
+/// Writes a scene as a `.vmf` file.
+///
+/// Every brushlet becomes a world solid and every layer after the first a
+/// visgroup. The same restrictions as [`save_map`](super::map::save_map) apply,
+/// so inverted brushlets and brushlets that subtract or intersect are rejected.
+/// `material_name` maps material indices to material names.
+pub fn save_vmf<F>(
+    scene: &BrusherScene,
+    settings: &MapSettings,
+    material_name: F,
+) -> Result<String, MapExportError>
+where
+    F: FnMut(MaterialIndex) -> String,
+{
+    let mut material_name = material_name;
+    let to_map = settings.to_world().inverse();
+    let mut next_id = 1;
+    let mut id = || {
+        next_id += 1;
+        (next_id - 1).to_string()
+    };
+
+    let mut version = KeyValues::new("versioninfo");
+    version.set("editorversion", "400");
+    version.set("editorbuild", "0");
+    version.set("mapversion", "1");
+    version.set("formatversion", "100");
+    version.set("prefab", "0");
+
+    let mut visgroups = KeyValues::new("visgroups");
+    let mut world = KeyValues::new("world");
+    world.set("id", id());
+    world.set("mapversion", "1");
+    world.set("classname", "worldspawn");
+
+    for (layer_idx, layer) in scene.layers.iter().enumerate() {
+        if layer_idx > 0 {
+            let mut visgroup = KeyValues::new("visgroup");
+            visgroup.set("name", layer.name.clone());
+            visgroup.set("visgroupid", layer_idx.to_string());
+            visgroup.set("color", "255 255 255");
+            visgroups.children.push(visgroup);
+        }
+
+        for brush in &layer.brushes {
+            for (brushlet_idx, brushlet) in brush.brushlets.iter().enumerate() {
+                if brushlet.settings.inverted {
+                    return Err(MapExportError::InvertedBrushlet {
+                        brush: brush.settings.name.clone(),
+                        brushlet: brushlet.settings.name.clone(),
+                    });
+                }
+                if brushlet_idx > 0 && brushlet.settings.operation != BooleanOp::Union {
+                    return Err(MapExportError::UnsupportedOperation {
+                        brush: brush.settings.name.clone(),
+                        brushlet: brushlet.settings.name.clone(),
+                        operation: brushlet.settings.operation,
+                    });
+                }
+                let surfaces =
+                    brushlet_surfaces(brushlet, &brush.settings.knives).ok_or_else(|| {
+                        MapExportError::NonConvexBrushlet {
+                            brush: brush.settings.name.clone(),
+                            brushlet: brushlet.settings.name.clone(),
+                        }
+                    })?;
+
+                let mut solid = KeyValues::new("solid");
+                solid.set("id", id());
+                let center = to_map.transform_point3(brushlet.aabb.center());
+                for surface in &surfaces {
+                    let surface = surface.with_texture(surface.texture_mapping());
+                    let plane = MapPlane::from_surface(
+                        &surface.transform(to_map),
+                        center,
+                        material_name(surface.material_idx),
+                        settings.texture_size,
+                    );
+                    solid.children.push(write_side(&plane, id()));
+                }
+
+                let mut editor = KeyValues::new("editor");
+                editor.set("color", "0 180 0");
+                if layer_idx > 0 {
+                    editor.set("visgroupid", layer_idx.to_string());
+                }
+                editor.set("visgroupshown", if layer.hidden { "0" } else { "1" });
+                editor.set("visgroupautoshown", "1");
+                solid.children.push(editor);
+
+                world.children.push(solid);
+            }
+        }
+    }
+
+    Ok(format!("{version}{visgroups}{world}"))
+}
+
+/// Adds a layer for every visgroup, including nested ones.
+fn add_visgroup_layers<'a>(
+    block: &'a KeyValues,
+    scene: &mut BrusherScene,
+    layer_ids: &mut HashMap<&'a str, usize>,
+) {
+    for visgroup in block.children_named("visgroup") {
+        if let Some(id) = visgroup.get("visgroupid") {
+            layer_ids.insert(id, scene.layers.len());
+        }
+        scene.layers.push(Layer {
+            name: visgroup.get("name").unwrap_or("Unnamed").to_string(),
+            brushes: Vec::new(),
+            hidden: false,
+        });
+        add_visgroup_layers(visgroup, scene, layer_ids);
+    }
+}
+
+fn parse_side(side: &KeyValues) -> Result<MapPlane, VmfError> {
+    let plane = numbers(side, "plane", 9)?;
+    let u = numbers(side, "uaxis", 5)?;
+    let v = numbers(side, "vaxis", 5)?;
+    let rotation = match side.get("rotation") {
+        Some(rotation) => rotation.parse().map_err(|_| VmfError::InvalidValue {
+            key: "rotation".to_string(),
+            value: rotation.to_string(),
+        })?,
+        None => 0.0,
+    };
+
+    Ok(MapPlane {
+        points: [
+            DVec3::new(plane[0], plane[1], plane[2]),
+            DVec3::new(plane[3], plane[4], plane[5]),
+            DVec3::new(plane[6], plane[7], plane[8]),
+        ],
+        texture: side.get("material").unwrap_or_default().to_string(),
+        axes: MapTextureAxes::Valve {
+            u_axis: DVec3::new(u[0], u[1], u[2]),
+            v_axis: DVec3::new(v[0], v[1], v[2]),
+            offset: DVec2::new(u[3], v[3]),
+            rotation,
+            scale: DVec2::new(u[4], v[4]),
+        },
+    })
+}
+
+fn write_side(plane: &MapPlane, id: String) -> KeyValues {
+    let mut side = KeyValues::new("side");
+    side.set("id", id);
+    let [a, b, c] = plane.points;
+    side.set(
+        "plane",
+        format!(
+            "({}) ({}) ({})",
+            format_vector(a),
+            format_vector(b),
+            format_vector(c)
+        ),
+    );
+    side.set("material", plane.texture.clone());
+    if let MapTextureAxes::Valve {
+        u_axis,
+        v_axis,
+        offset,
+        rotation,
+        scale,
+    } = plane.axes
+    {
+        side.set(
+            "uaxis",
+            format!(
+                "[{} {}] {}",
+                format_vector(u_axis),
+                format_number(offset.x),
+                format_number(scale.x)
+            ),
+        );
+        side.set(
+            "vaxis",
+            format!(
+                "[{} {}] {}",
+                format_vector(v_axis),
+                format_number(offset.y),
+                format_number(scale.y)
+            ),
+        );
+        side.set("rotation", format_number(rotation));
+    }
+    side.set("lightmapscale", "16");
+    side.set("smoothing_groups", "0");
+    side
+}
+
+/// Reads a value made of `count` numbers, ignoring brackets and parentheses.
+fn numbers(block: &KeyValues, key: &str, count: usize) -> Result<Vec<f64>, VmfError> {
+    let value = block.get(key).unwrap_or_default();
+    let invalid = || VmfError::InvalidValue {
+        key: key.to_string(),
+        value: value.to_string(),
+    };
+    let numbers = value
+        .split(|c: char| c.is_whitespace() || "()[]".contains(c))
+        .filter(|part| !part.is_empty())
+        .map(|part| part.parse::<f64>().map_err(|_| invalid()))
+        .collect::<Result<Vec<_>, _>>()?;
+    if numbers.len() != count {
+        return Err(invalid());
+    }
+    Ok(numbers)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        brush::brushlet::{tests::test_settings, Brushlet},
+        primitives::{Cuboid, CuboidMaterialIndices},
+    };
+
+    const VMF: &str = r#"versioninfo
+{
+	"editorversion" "400"
+	"formatversion" "100"
+}
+visgroups
+{
+	visgroup
+	{
+		"name" "Walls"
+		"visgroupid" "3"
+		"color" "255 0 0"
+		visgroup
+		{
+			"name" "Hidden"
+			"visgroupid" "4"
+			"color" "0 255 0"
+		}
+	}
+}
+world
+{
+	"id" "1"
+	"classname" "worldspawn"
+	solid
+	{
+		"id" "2"
+		side
+		{
+			"id" "1"
+			"plane" "(0 0 64) (64 0 64) (64 -64 64)"
+			"material" "DEV/FLOOR"
+			"uaxis" "[1 0 0 16] 0.25"
+			"vaxis" "[0 -1 0 0] 0.25"
+			"rotation" "0"
+			"lightmapscale" "16"
+			"smoothing_groups" "0"
+		}
+		side
+		{
+			"id" "2"
+			"plane" "(0 -64 0) (64 -64 0) (64 0 0)"
+			"material" "DEV/FLOOR"
+			"uaxis" "[1 0 0 0] 0.25"
+			"vaxis" "[0 -1 0 0] 0.25"
+			"rotation" "0"
+		}
+		side
+		{
+			"id" "3"
+			"plane" "(0 0 64) (0 -64 64) (0 -64 0)"
+			"material" "DEV/WALL"
+			"uaxis" "[0 1 0 0] 0.25"
+			"vaxis" "[0 0 -1 0] 0.25"
+			"rotation" "0"
+		}
+		side
+		{
+			"id" "4"
+			"plane" "(64 0 0) (64 -64 0) (64 -64 64)"
+			"material" "DEV/WALL"
+			"uaxis" "[0 1 0 0] 0.25"
+			"vaxis" "[0 0 -1 0] 0.25"
+			"rotation" "0"
+		}
+		side
+		{
+			"id" "5"
+			"plane" "(64 0 64) (0 0 64) (0 0 0)"
+			"material" "DEV/WALL"
+			"uaxis" "[1 0 0 0] 0.25"
+			"vaxis" "[0 0 -1 0] 0.25"
+			"rotation" "0"
+		}
+		side
+		{
+			"id" "6"
+			"plane" "(64 -64 0) (0 -64 0) (0 -64 64)"
+			"material" "DEV/WALL"
+			"uaxis" "[1 0 0 0] 0.25"
+			"vaxis" "[0 0 -1 0] 0.25"
+			"rotation" "0"
+		}
+		editor
+		{
+			"color" "0 180 0"
+			"visgroupid" "4"
+			"visgroupshown" "0"
+			"visgroupautoshown" "1"
+		}
+	}
+}
+cameras
+{
+	"activecamera" "-1"
+}
+"#;
+
+    #[test]
+    fn test_parse_key_values() {
+        let blocks = KeyValues::parse(VMF).unwrap();
+        assert_eq!(blocks.len(), 4);
+        assert_eq!(blocks[0].get("editorversion"), Some("400"));
+
+        let solid = blocks[2].child("solid").unwrap();
+        assert_eq!(solid.children_named("side").count(), 6);
+        assert_eq!(solid.child("editor").unwrap().get("visgroupid"), Some("4"));
+
+        assert_eq!(
+            KeyValues::parse("world\n{\n\"id\" \"1\"\n").unwrap_err(),
+            VmfError::UnexpectedEndOfFile
+        );
+    }
+
+    #[test]
+    fn test_load_vmf() {
+        let settings = MapSettings {
+            scale: 1.0 / 64.0,
+            y_up: false,
+            texture_size: DVec2::splat(128.0),
+        };
+        let scene = load_vmf(VMF, &settings, |name| match name {
+            "DEV/FLOOR" => 1,
+            _ => 2,
+        })
+        .unwrap();
+
+        assert_eq!(scene.layers.len(), 3);
+        assert_eq!(scene.layers[1].name, "Walls");
+        assert_eq!(scene.layers[2].name, "Hidden");
+        assert!(scene.layers[2].hidden);
+        assert!(!scene.layers[1].hidden);
+
+        let brushlet = &scene.layers[2].brushes[0].brushlets[0];
+        assert_eq!(brushlet.polygons.len(), 6);
+        assert!((brushlet.aabb.min - DVec3::new(0.0, -1.0, 0.0)).length() < 1e-9);
+        assert!((brushlet.aabb.max - DVec3::new(1.0, 0.0, 1.0)).length() < 1e-9);
+
+        let top = brushlet
+            .polygons
+            .iter()
+            .find(|polygon| polygon.surface.normal.z > 0.5)
+            .unwrap();
+        assert_eq!(top.surface.material_idx, 1);
+        // 0.25 units per texel on a 128 texel texture, offset by 16 texels
+        let uv = top.surface.compute_uv(DVec3::new(0.5, -0.25, 1.0));
+        let expected = DVec2::new((32.0 / 0.25 + 16.0) / 128.0, (16.0 / 0.25) / 128.0);
+        assert!((uv - expected).length() < 1e-9);
+
+        // Solids hidden in the editor are kept in a hidden layer
+        let source = VMF
+            .replacen("\tsolid\n", "\thidden\n\t{\n\tsolid\n", 1)
+            .replacen("\t}\n}\ncameras", "\t}\n\t}\n}\ncameras", 1);
+        let scene = load_vmf(&source, &settings, |_| 0).unwrap();
+        assert_eq!(scene.layers.len(), 4);
+        assert!(scene.layers[2].brushes.is_empty());
+        assert_eq!(scene.layers[3].name, HIDDEN_LAYER_NAME);
+        assert!(scene.layers[3].hidden);
+        assert_eq!(scene.layers[3].brushes[0].brushlets[0].polygons.len(), 6);
+    }
+
+    #[test]
+    fn test_vmf_round_trip() {
+        let mut brush = Brush::new("Box");
+        brush.brushlets.push(Brushlet::from_cuboid(
+            Cuboid {
+                origin: DVec3::new(1.0, 2.0, 3.0),
+                width: 2.0,
+                height: 1.0,
+                depth: 4.0,
+                material_indices: CuboidMaterialIndices {
+                    front: 1,
+                    back: 2,
+                    left: 3,
+                    right: 4,
+                    top: 5,
+                    bottom: 6,
+                },
+            },
+            test_settings(),
+        ));
+        let mut scene = BrusherScene::new();
+        scene.layers.push(Layer {
+            name: DEFAULT_LAYER_NAME.to_string(),
+            brushes: Vec::new(),
+            hidden: false,
+        });
+        scene.layers.push(Layer {
+            name: "Boxes".to_string(),
+            brushes: vec![brush],
+            hidden: true,
+        });
+
+        let settings = MapSettings::default();
+        let source = save_vmf(&scene, &settings, |idx| format!("DEV/MAT{idx}")).unwrap();
+        let loaded = load_vmf(&source, &settings, |name| name[7..].parse().unwrap()).unwrap();
+
+        assert_eq!(loaded.layers.len(), 2);
+        assert_eq!(loaded.layers[1].name, "Boxes");
+        assert!(loaded.layers[1].hidden);
+
+        let original = &scene.layers[1].brushes[0].brushlets[0];
+        let brushlet = &loaded.layers[1].brushes[0].brushlets[0];
+        assert!((brushlet.aabb.min - original.aabb.min).length() < 1e-9);
+        assert!((brushlet.aabb.max - original.aabb.max).length() < 1e-9);
+
+        for polygon in &original.polygons {
+            let loaded_polygon = brushlet
+                .polygons
+                .iter()
+                .find(|other| other.surface == polygon.surface)
+                .unwrap();
+            assert_eq!(
+                loaded_polygon.surface.material_idx,
+                polygon.surface.material_idx
+            );
+            let point = polygon.vertices[0].pos;
+            let uv = loaded_polygon.surface.compute_uv(point);
+            assert!((uv - polygon.surface.compute_uv(point)).length() < 1e-9);
+        }
+
+        scene.layers[1].brushes[0].brushlets[0].settings.inverted = true;
+        assert_eq!(
+            save_vmf(&scene, &settings, |_| "DEV/MAT".to_string()).unwrap_err(),
+            MapExportError::InvertedBrushlet {
+                brush: "Box".to_string(),
+                brushlet: "Test".to_string(),
+            }
+        );
+    }
+}
@@ -1,20 +1,27 @@
 pub mod broadphase;
 pub mod brush;
+pub mod formats;
+pub mod mesh;
 pub mod polygon;
+pub mod predicates;
 pub mod primitives;
 pub mod scene;
 pub mod surface;
+pub mod tolerance;
 mod util;
 
 pub mod prelude {
     pub use crate::brush::{
         brushlet::{Brushlet, BrushletSettings},
-        operations::Knife,
-        BooleanOp, Brush, BrushError, BrushSettings, BrushletOp, MeshData,
+        operations::{Bevel, BevelEdges, Hollow, HollowJoint, Knife},
+        BooleanOp, Brush, BrushError, BrushSettings, BrushletOp, MeshData, SmoothNormalsSettings,
     };
+    pub use crate::mesh::{BatchedMesh, MeshBatch, DEFAULT_WELD_TOLERANCE};
     pub use crate::polygon::*;
     pub use crate::primitives::*;
+    pub use crate::scene::{BrusherScene, Layer};
     pub use crate::surface::*;
+    pub use crate::tolerance::{PlaneTest, Tolerance};
 
     #[cfg(not(feature = "bevy"))]
     pub use glam::{DAffine3, DVec2, DVec3};
@@ -0,0 +1,321 @@
+//! Engine agnostic render meshes.
+//!
+//! [`BatchedMesh`] merges all polygons sharing a material index into a single
+//! indexed vertex buffer, welding vertices with matching attributes, and stores
+//! everything as flat `f32` arrays ready to upload to a renderer.
+
+use std::collections::{BTreeMap, HashMap};
+
+#[cfg(feature = "bevy")]
+use bevy::render::{
+    mesh::{Indices, Mesh, PrimitiveTopology},
+    render_asset::RenderAssetUsages,
+};
+
+use crate::{
+    brush::{MaterialIndex, MeshData},
+    tolerance::Tolerance,
+};
+
+/// The default distance within which vertex attributes are welded.
+pub const DEFAULT_WELD_TOLERANCE: f64 = 1e-5;
+
+/// The vertices and triangles of a single material.
+///
+/// # Fields
+/// * `material_idx` - The material index shared by every triangle
+/// * `positions` - Vertex positions, 3 floats per vertex
+/// * `normals` - Vertex normals, 3 floats per vertex
+/// * `uvs` - Vertex texture coordinates, 2 floats per vertex
+/// * `indices` - Triangle list indices into the vertices
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MeshBatch {
+    pub material_idx: MaterialIndex,
+    pub positions: Vec<f32>,
+    pub normals: Vec<f32>,
+    pub uvs: Vec<f32>,
+    pub indices: Vec<u32>,
+}
+
+impl MeshBatch {
+    pub fn vertex_count(&self) -> usize {
+        self.positions.len() / 3
+    }
+
+    pub fn triangle_count(&self) -> usize {
+        self.indices.len() / 3
+    }
+}
+
+/// An indexed mesh with one [`MeshBatch`] per material index, sorted by material index.
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BatchedMesh {
+    pub batches: Vec<MeshBatch>,
+}
+
+impl BatchedMesh {
+    /// Batches the polygons of the mesh data by material.
+    ///
+    /// Vertices are welded when their position, normal and UV are all within
+    /// `weld_tolerance` of an earlier vertex. A tolerance of zero or less
+    /// only welds vertices that are exactly equal.
+    pub fn from_mesh_data(mesh_data: &MeshData, weld_tolerance: f64) -> Self {
+        Self::from_mesh_data_with_tolerance(mesh_data, weld_tolerance, &Tolerance::default())
+    }
+
+    /// Like [`BatchedMesh::from_mesh_data`], with the tolerance used to
+    /// triangulate the polygons.
+    pub fn from_mesh_data_with_tolerance(
+        mesh_data: &MeshData,
+        weld_tolerance: f64,
+        tolerance: &Tolerance,
+    ) -> Self {
+        let mut batches: BTreeMap<MaterialIndex, (MeshBatch, Welder)> = BTreeMap::new();
+
+        for polygon in &mesh_data.polygons {
+            let material_idx = polygon.surface.material_idx;
+            let (batch, welded) = batches.entry(material_idx).or_insert_with(|| {
+                (
+                    MeshBatch {
+                        material_idx,
+                        ..Default::default()
+                    },
+                    Welder::new(weld_tolerance),
+                )
+            });
+
+            let vertex_indices: Vec<u32> = polygon
+                .vertices
+                .iter()
+                .map(|vertex| {
+                    let uv = polygon.surface.compute_uv(vertex.pos);
+                    let attributes = [
+                        vertex.pos.x,
+                        vertex.pos.y,
+                        vertex.pos.z,
+                        vertex.normal.x,
+                        vertex.normal.y,
+                        vertex.normal.z,
+                        uv.x,
+                        uv.y,
+                    ];
+                    welded.find(&attributes).unwrap_or_else(|| {
+                        let [px, py, pz, nx, ny, nz, u, v] = attributes.map(|value| value as f32);
+                        batch.positions.extend([px, py, pz]);
+                        batch.normals.extend([nx, ny, nz]);
+                        batch.uvs.extend([u, v]);
+                        welded.insert(attributes)
+                    })
+                })
+                .collect();
+
+            batch.indices.extend(
+                polygon
+                    .indices_with_tolerance(tolerance)
+                    .into_iter()
+                    .map(|idx| vertex_indices[idx as usize]),
+            );
+        }
+
+        Self {
+            batches: batches.into_values().map(|(batch, _)| batch).collect(),
+        }
+    }
+
+    #[cfg(feature = "bevy")]
+    pub fn to_bevy_meshes(&self) -> Vec<(Mesh, MaterialIndex)> {
+        self.batches
+            .iter()
+            .map(|batch| {
+                let mut mesh = Mesh::new(
+                    PrimitiveTopology::TriangleList,
+                    RenderAssetUsages::default(),
+                );
+                mesh.insert_attribute(
+                    Mesh::ATTRIBUTE_POSITION,
+                    batch
+                        .positions
+                        .chunks(3)
+                        .map(|p| [p[0], p[1], p[2]])
+                        .collect::<Vec<_>>(),
+                );
+                mesh.insert_attribute(
+                    Mesh::ATTRIBUTE_NORMAL,
+                    batch
+                        .normals
+                        .chunks(3)
+                        .map(|n| [n[0], n[1], n[2]])
+                        .collect::<Vec<_>>(),
+                );
+                mesh.insert_attribute(
+                    Mesh::ATTRIBUTE_UV_0,
+                    batch
+                        .uvs
+                        .chunks(2)
+                        .map(|uv| [uv[0], uv[1]])
+                        .collect::<Vec<_>>(),
+                );
+                mesh.insert_indices(Indices::U32(batch.indices.clone()));
+                (mesh, batch.material_idx)
+            })
+            .collect()
+    }
+}
+
+/// The vertices of a batch, bucketed by position so welding only compares
+/// vertices in the same or a neighbouring grid cell.
+struct Welder {
+    tolerance: f64,
+    cells: HashMap<[i64; 3], Vec<u32>>,
+    attributes: Vec<[f64; 8]>,
+}
+
+impl Welder {
+    fn new(tolerance: f64) -> Self {
+        Self {
+            tolerance,
+            cells: HashMap::new(),
+            attributes: Vec::new(),
+        }
+    }
+
+    fn cell(&self, attributes: &[f64; 8]) -> [i64; 3] {
+        [0, 1, 2].map(|i| weld_key(attributes[i], self.tolerance))
+    }
+
+    /// The index of an earlier vertex whose attributes are all within the tolerance.
+    fn find(&self, attributes: &[f64; 8]) -> Option<u32> {
+        // Values within the tolerance round to the same or a neighbouring cell
+        let reach = if self.tolerance > 0.0 { 1 } else { 0 };
+        let cell = self.cell(attributes);
+        let tolerance = self.tolerance.max(0.0);
+        for x in -reach..=reach {
+            for y in -reach..=reach {
+                for z in -reach..=reach {
+                    let Some(indices) = self.cells.get(&[cell[0] + x, cell[1] + y, cell[2] + z])
+                    else {
+                        continue;
+                    };
+                    let found = indices.iter().find(|idx| {
+                        self.attributes[**idx as usize]
+                            .iter()
+                            .zip(attributes)
+                            .all(|(a, b)| (a - b).abs() <= tolerance)
+                    });
+                    if found.is_some() {
+                        return found.copied();
+                    }
+                }
+            }
+        }
+        None
+    }
+
+    /// Adds a vertex and returns its index.
+    fn insert(&mut self, attributes: [f64; 8]) -> u32 {
+        let idx = self.attributes.len() as u32;
+        self.cells
+            .entry(self.cell(&attributes))
+            .or_default()
+            .push(idx);
+        self.attributes.push(attributes);
+        idx
+    }
+}
+
+fn weld_key(value: f64, tolerance: f64) -> i64 {
+    if tolerance > 0.0 {
+        (value / tolerance).round() as i64
+    } else {
+        // -0.0 and 0.0 should weld
+        (value + 0.0).to_bits() as i64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{brush::brushlet::tests::test_settings, prelude::*};
+
+    #[test]
+    fn test_from_mesh_data() {
+        let brushlet = Brushlet::from_cuboid(
+            Cuboid {
+                origin: DVec3::ZERO,
+                width: 1.0,
+                height: 1.0,
+                depth: 1.0,
+                material_indices: CuboidMaterialIndices {
+                    front: 2,
+                    back: 2,
+                    left: 2,
+                    right: 2,
+                    top: 0,
+                    bottom: 0,
+                },
+            },
+            test_settings(),
+        );
+        let mut mesh_data = brushlet.to_mesh_data();
+
+        // Split a side into two coplanar quads sharing an edge
+        let side = mesh_data
+            .polygons
+            .iter()
+            .position(|polygon| polygon.surface.material_idx == 2)
+            .unwrap();
+        let polygon = mesh_data.polygons.remove(side);
+        let [a, b, c, d] = [0, 1, 2, 3].map(|i| polygon.vertices[i].clone());
+        let ab = a.interpolate(&b, 0.5);
+        let cd = c.interpolate(&d, 0.5);
+        for vertices in [vec![a, ab.clone(), cd.clone(), d], vec![ab, b, c, cd]] {
+            mesh_data.polygons.push(Polygon {
+                vertices,
+                surface: polygon.surface,
+            });
+        }
+
+        let mesh = BatchedMesh::from_mesh_data(&mesh_data, DEFAULT_WELD_TOLERANCE);
+        assert_eq!(mesh.batches.len(), 2);
+        assert_eq!(mesh.batches[0].material_idx, 0);
+        assert_eq!(mesh.batches[1].material_idx, 2);
+
+        assert_eq!(mesh.batches[0].vertex_count(), 8);
+        assert_eq!(mesh.batches[0].triangle_count(), 4);
+        // 3 untouched sides with 4 vertices each, plus 6 for the split side
+        assert_eq!(mesh.batches[1].vertex_count(), 18);
+        assert_eq!(mesh.batches[1].triangle_count(), 10);
+
+        for batch in &mesh.batches {
+            assert_eq!(batch.normals.len(), batch.positions.len());
+            assert_eq!(batch.uvs.len(), batch.vertex_count() * 2);
+            assert!(batch
+                .indices
+                .iter()
+                .all(|idx| (*idx as usize) < batch.vertex_count()));
+        }
+    }
+
+    #[test]
+    fn test_weld_across_grid_cells() {
+        // The shared edge of two quads lies either side of a grid cell boundary
+        let edge = 1.5 * DEFAULT_WELD_TOLERANCE;
+        let quad = |min_x: f64, max_x: f64| {
+            let vertices = [(min_x, 0.0), (min_x, 1.0), (max_x, 1.0), (max_x, 0.0)]
+                .map(|(x, z)| Vertex::new(DVec3::new(x, 0.0, z), DVec3::Y));
+            Polygon::new(vertices.to_vec(), 0)
+        };
+        let mesh_data = MeshData {
+            polygons: vec![quad(-1.0, edge - 1e-7), quad(edge + 1e-7, 1.0)],
+        };
+
+        let mesh = BatchedMesh::from_mesh_data(&mesh_data, DEFAULT_WELD_TOLERANCE);
+        assert_eq!(mesh.batches[0].vertex_count(), 6);
+        assert_eq!(mesh.batches[0].triangle_count(), 4);
+
+        let exact = BatchedMesh::from_mesh_data(&mesh_data, 0.0);
+        assert_eq!(exact.batches[0].vertex_count(), 8);
+    }
+}
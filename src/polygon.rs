@@ -1,12 +1,18 @@
-use super::surface::Surface;
+use super::{
+    brush::BrushError,
+    surface::Surface,
+    tolerance::Tolerance,
+    util::{is_proper_triangle_2d, signed_area_2d, triangulate_2d},
+};
 
 #[cfg(feature = "bevy")]
-use bevy::math::{DAffine3, DVec3};
+use bevy::math::{DAffine3, DVec2, DVec3};
 
 #[cfg(not(feature = "bevy"))]
-use glam::{DAffine3, DVec3};
+use glam::{DAffine3, DVec2, DVec3};
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[cfg_attr(feature = "bevy", derive(bevy::prelude::Reflect))]
 pub struct Polygon {
     pub vertices: Vec<Vertex>,
@@ -14,6 +20,9 @@ pub struct Polygon {
 }
 
 impl Polygon {
+    /// Creates a polygon on the plane of its first three vertices.
+    ///
+    /// Panics with fewer than 3 vertices, see [`Polygon::try_new`] for checked input.
     pub fn new(vertices: Vec<Vertex>, material_index: usize) -> Self {
         let mut surface = Surface::from_points(
             vertices[0].pos,
@@ -27,6 +36,64 @@ impl Polygon {
         Self { vertices, surface }
     }
 
+    /// Creates a polygon, checking that the vertices are finite and form a
+    /// flat convex polygon with some area.
+    ///
+    /// The plane is fitted to all the vertices, so unlike [`Polygon::new`] the
+    /// first three may be collinear.
+    pub fn try_new(vertices: Vec<Vertex>, material_index: usize) -> Result<Self, BrushError> {
+        Self::try_new_with_tolerance(vertices, material_index, &Tolerance::default())
+    }
+
+    /// Like [`Polygon::try_new`], with the tolerance used for the flatness,
+    /// area and convexity checks.
+    pub fn try_new_with_tolerance(
+        vertices: Vec<Vertex>,
+        material_index: usize,
+        tolerance: &Tolerance,
+    ) -> Result<Self, BrushError> {
+        if vertices
+            .iter()
+            .any(|vertex| !vertex.pos.is_finite() || !vertex.normal.is_finite())
+        {
+            return Err(BrushError::NonFiniteInput);
+        }
+        if vertices.len() < 3 {
+            return Err(BrushError::DegeneratePolygon);
+        }
+
+        // Newell's method, twice the area vector of the polygon
+        let count = vertices.len();
+        let area: DVec3 = (0..count)
+            .map(|i| vertices[i].pos.cross(vertices[(i + 1) % count].pos))
+            .sum();
+        if area.length() / 2.0 < tolerance.epsilon * tolerance.epsilon {
+            return Err(BrushError::DegeneratePolygon);
+        }
+        let normal = area.normalize();
+        let center = vertices.iter().map(|vertex| vertex.pos).sum::<DVec3>() / count as f64;
+        let surface = Surface::new(normal, normal.dot(center), material_index);
+
+        if vertices
+            .iter()
+            .any(|vertex| tolerance.plane_distance(&surface, vertex.pos) != 0.0)
+        {
+            return Err(BrushError::DegeneratePolygon);
+        }
+        let convex = (0..count).all(|i| {
+            let prev = vertices[(i + count - 1) % count].pos;
+            let pos = vertices[i].pos;
+            let next = vertices[(i + 1) % count].pos;
+            (pos - prev).cross(next - pos).dot(normal)
+                >= -tolerance.epsilon * (next - prev).length()
+        });
+        if !convex {
+            return Err(BrushError::NonConvex);
+        }
+
+        Ok(Self { vertices, surface })
+    }
+
     pub fn flip(&mut self) {
         for vertex in &mut self.vertices {
             vertex.flip();
@@ -35,17 +102,49 @@ impl Polygon {
         self.surface.flip();
     }
 
+    /// Triangulates the polygon by ear clipping.
+    ///
+    /// Unlike a fan this handles vertices in the middle of straight edges, such
+    /// as the ones added by [`MeshData::repair_t_junctions`], without producing
+    /// zero area triangles.
+    ///
+    /// [`MeshData::repair_t_junctions`]: crate::brush::MeshData::repair_t_junctions
     pub fn indices(&self) -> Vec<u32> {
-        let mut indices = Vec::new();
-        let vertex_count = self.vertices.len() as u32;
+        self.indices_with_tolerance(&Tolerance::default())
+    }
+
+    /// Like [`Polygon::indices`], triangles whose corners are within `epsilon`
+    /// of a line are dropped.
+    pub fn indices_with_tolerance(&self, tolerance: &Tolerance) -> Vec<u32> {
+        if self.vertices.len() < 3 {
+            return Vec::new();
+        }
+        if self.vertices.len() == 3 {
+            return vec![0, 1, 2];
+        }
 
-        for i in 1..vertex_count - 1 {
-            indices.push(0);
-            indices.push(i);
-            indices.push(i + 1);
+        let mut outline = self.outline_2d();
+        let reversed = signed_area_2d(&outline) < 0.0;
+        if reversed {
+            outline.reverse();
         }
 
-        indices
+        let count = outline.len();
+        triangulate_2d(&outline, tolerance)
+            .into_iter()
+            .filter(|[a, b, c]| {
+                is_proper_triangle_2d(outline[*a], outline[*b], outline[*c], tolerance)
+            })
+            .flat_map(|[a, b, c]| {
+                // Keep the winding of the vertices when they had to be reversed
+                if reversed {
+                    [count - 1 - c, count - 1 - b, count - 1 - a]
+                } else {
+                    [a, b, c]
+                }
+                .map(|idx| idx as u32)
+            })
+            .collect()
     }
 
     pub fn normals(&self) -> Vec<DVec3> {
@@ -111,14 +210,66 @@ impl Polygon {
     }
 
     pub fn contains_point(&self, point: DVec3) -> bool {
+        self.contains_point_with_tolerance(point, &Tolerance::default())
+    }
+
+    /// Whether the point lies on the polygon, within `epsilon` of its plane and
+    /// inside or on its boundary. Works for concave polygons too.
+    pub fn contains_point_with_tolerance(&self, point: DVec3, tolerance: &Tolerance) -> bool {
+        if self.vertices.len() < 3 {
+            return false;
+        }
         let normal = self.surface.normal;
         let d = -normal.dot(self.vertices[0].pos);
         let distance = normal.dot(point) + d;
-        distance.abs() < 0.0001
+        if distance.abs() > tolerance.epsilon {
+            return false;
+        }
+
+        let outline = self.outline_2d();
+        let point = self.project_2d(point);
+        let count = outline.len();
+        let mut inside = false;
+        for i in 0..count {
+            let (a, b) = (outline[i], outline[(i + 1) % count]);
+
+            // Points on the boundary count as inside
+            let edge = b - a;
+            let t = ((point - a).dot(edge) / edge.length_squared()).clamp(0.0, 1.0);
+            if (a + edge * t).distance(point) <= tolerance.epsilon {
+                return true;
+            }
+
+            // Crossing number, counts the edges crossed by a ray along +X
+            if (a.y > point.y) != (b.y > point.y) {
+                let x = a.x + (point.y - a.y) / (b.y - a.y) * (b.x - a.x);
+                if point.x < x {
+                    inside = !inside;
+                }
+            }
+        }
+        inside
+    }
+
+    fn project_2d(&self, point: DVec3) -> DVec2 {
+        let transform = self.compute_transform();
+        DVec2::new(
+            point.dot(transform.matrix3.x_axis),
+            point.dot(transform.matrix3.y_axis),
+        )
+    }
+
+    /// The vertices in the 2D space of the plane, see [`Polygon::compute_transform`].
+    fn outline_2d(&self) -> Vec<DVec2> {
+        self.vertices
+            .iter()
+            .map(|vertex| self.project_2d(vertex.pos))
+            .collect()
     }
 }
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[cfg_attr(feature = "bevy", derive(bevy::prelude::Reflect))]
 pub struct Vertex {
     pub pos: DVec3,
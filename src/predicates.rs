@@ -0,0 +1,101 @@
+//! Adaptive precision geometric predicates.
+//!
+//! The plane side test evaluates `normal · point - distance` in floating point
+//! first and only falls back to exact arithmetic when the result is too close
+//! to zero for its sign to be trusted, in the style of Shewchuk's predicates.
+//! The inputs are taken as exact, so the sign is correct for the given numbers.
+
+#[cfg(feature = "bevy")]
+use bevy::math::DVec3;
+
+#[cfg(not(feature = "bevy"))]
+use glam::DVec3;
+
+/// Bounds the rounding error of a sum of four products, relative to the sum
+/// of their magnitudes.
+const PLANE_SIDE_ERROR_BOUND: f64 = 8.0 * f64::EPSILON;
+
+/// Which side of the plane `normal · p = distance` a point is on.
+///
+/// Returns a value with the exact sign of `normal · point - distance`, positive
+/// in front of the plane, negative behind it and zero only when the point is
+/// exactly on it. The magnitude is only an approximation of the distance.
+pub fn plane_side(normal: DVec3, distance: f64, point: DVec3) -> f64 {
+    let products = [normal.x * point.x, normal.y * point.y, normal.z * point.z];
+    let approximate = products[0] + products[1] + products[2] - distance;
+    let magnitude = products.iter().map(|p| p.abs()).sum::<f64>() + distance.abs();
+    if approximate.abs() > PLANE_SIDE_ERROR_BOUND * magnitude {
+        return approximate;
+    }
+
+    // Every product is exactly the sum of its rounded value and its error
+    let mut expansion = Vec::with_capacity(7);
+    for (a, b) in [
+        (normal.x, point.x),
+        (normal.y, point.y),
+        (normal.z, point.z),
+    ] {
+        let (product, error) = two_product(a, b);
+        grow_expansion(&mut expansion, error);
+        grow_expansion(&mut expansion, product);
+    }
+    grow_expansion(&mut expansion, -distance);
+
+    // The components don't overlap and grow in magnitude, so the last one
+    // decides the sign
+    expansion.last().copied().unwrap_or(0.0)
+}
+
+/// `a + b` as a rounded sum and its exact rounding error.
+fn two_sum(a: f64, b: f64) -> (f64, f64) {
+    let sum = a + b;
+    let b_virtual = sum - a;
+    let a_virtual = sum - b_virtual;
+    (sum, (a - a_virtual) + (b - b_virtual))
+}
+
+/// `a * b` as a rounded product and its exact rounding error.
+fn two_product(a: f64, b: f64) -> (f64, f64) {
+    let product = a * b;
+    (product, a.mul_add(b, -product))
+}
+
+/// Adds a value to a nonoverlapping expansion sorted by increasing magnitude,
+/// dropping zero components.
+fn grow_expansion(expansion: &mut Vec<f64>, value: f64) {
+    let mut carry = value;
+    let mut grown = Vec::with_capacity(expansion.len() + 1);
+    for component in expansion.iter() {
+        let (sum, error) = two_sum(carry, *component);
+        if error != 0.0 {
+            grown.push(error);
+        }
+        carry = sum;
+    }
+    if carry != 0.0 {
+        grown.push(carry);
+    }
+    *expansion = grown;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_plane_side() {
+        let normal = DVec3::new(1.0, 1.0, 0.0);
+        assert!(plane_side(normal, 1.0, DVec3::new(1.0, 1.0, 5.0)) > 0.0);
+        assert!(plane_side(normal, 1.0, DVec3::new(0.0, 0.0, 5.0)) < 0.0);
+        assert_eq!(plane_side(normal, 1.0, DVec3::new(0.25, 0.75, 5.0)), 0.0);
+
+        // The rounded sum loses the small term entirely
+        let normal = DVec3::ONE;
+        let (big, small) = (2f64.powi(60), 1.0);
+        let point = DVec3::new(big, small, -big);
+        assert_eq!(big + small - big, 0.0);
+        assert!(plane_side(normal, 0.5, point) > 0.0);
+        assert!(plane_side(normal, 1.5, point) < 0.0);
+        assert_eq!(plane_side(normal, 1.0, point), 0.0);
+    }
+}
@@ -1,8 +1,8 @@
 #[cfg(feature = "bevy")]
-use bevy::math::DVec3;
+use bevy::math::{DVec2, DVec3};
 
 #[cfg(not(feature = "bevy"))]
-use glam::DVec3;
+use glam::{DVec2, DVec3};
 
 // A cuboid material indices
 ///
@@ -14,6 +14,7 @@ use glam::DVec3;
 /// * `left` - The material index for the left face
 /// * `right` - The material index for the right face
 #[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct CuboidMaterialIndices {
     pub top: usize,
     pub bottom: usize,
@@ -32,6 +33,7 @@ pub struct CuboidMaterialIndices {
 /// * `depth` - The depth of the cuboid (z-axis)
 /// * `material_indices` - The material indices for each face of the cuboid
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Cuboid {
     pub origin: DVec3,
     pub width: f64,
@@ -39,3 +41,399 @@ pub struct Cuboid {
     pub depth: f64,
     pub material_indices: CuboidMaterialIndices,
 }
+
+/// A cylinder material indices
+///
+/// # Fields
+/// * `sides` - The material index for the side faces
+/// * `top` - The material index for the cap at the end of the axis
+/// * `bottom` - The material index for the cap at the start of the axis
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CylinderMaterialIndices {
+    pub sides: usize,
+    pub top: usize,
+    pub bottom: usize,
+}
+
+/// A cylinder, approximated by a prism with flat sides
+///
+/// # Fields
+/// * `origin` - The center of the cylinder
+/// * `radius` - The distance from the axis to the corners of the sides
+/// * `height` - The length of the cylinder along its axis
+/// * `segments` - The number of sides, at least 3
+/// * `axis` - The direction from the bottom cap to the top cap
+/// * `material_indices` - The material indices for the sides and caps
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Cylinder {
+    pub origin: DVec3,
+    pub radius: f64,
+    pub height: f64,
+    pub segments: usize,
+    pub axis: DVec3,
+    pub material_indices: CylinderMaterialIndices,
+}
+
+/// A cone material indices
+///
+/// # Fields
+/// * `sides` - The material index for the side faces
+/// * `top` - The material index for the top cap, only used when the cone is truncated
+/// * `bottom` - The material index for the base
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ConeMaterialIndices {
+    pub sides: usize,
+    pub top: usize,
+    pub bottom: usize,
+}
+
+/// A cone, approximated by flat sides, that can be truncated into a frustum
+///
+/// # Fields
+/// * `origin` - The center of the cone, halfway between the base and the tip
+/// * `radius` - The radius of the base
+/// * `top_radius` - The radius of the top cap for a frustum, `None` for a pointed cone
+/// * `height` - The length of the cone along its axis
+/// * `segments` - The number of sides, at least 3
+/// * `axis` - The direction from the base to the tip
+/// * `material_indices` - The material indices for the sides and caps
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Cone {
+    pub origin: DVec3,
+    pub radius: f64,
+    pub top_radius: Option<f64>,
+    pub height: f64,
+    pub segments: usize,
+    pub axis: DVec3,
+    pub material_indices: ConeMaterialIndices,
+}
+
+/// A pyramid material indices
+///
+/// # Fields
+/// * `front` - The material index for the face sloping towards +Z
+/// * `back` - The material index for the face sloping towards -Z
+/// * `left` - The material index for the face sloping towards -X
+/// * `right` - The material index for the face sloping towards +X
+/// * `bottom` - The material index for the base
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PyramidMaterialIndices {
+    pub front: usize,
+    pub back: usize,
+    pub left: usize,
+    pub right: usize,
+    pub bottom: usize,
+}
+
+/// A pyramid with a rectangular base and its tip above the center of the base
+///
+/// # Fields
+/// * `origin` - The center of the pyramid's bounding box
+/// * `width` - The width of the base (x-axis)
+/// * `height` - The height of the tip above the base (y-axis)
+/// * `depth` - The depth of the base (z-axis)
+/// * `material_indices` - The material indices for each face of the pyramid
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Pyramid {
+    pub origin: DVec3,
+    pub width: f64,
+    pub height: f64,
+    pub depth: f64,
+    pub material_indices: PyramidMaterialIndices,
+}
+
+/// A wedge material indices
+///
+/// # Fields
+/// * `slope` - The material index for the sloped face
+/// * `back` - The material index for the vertical back face
+/// * `bottom` - The material index for the bottom face
+/// * `left` - The material index for the left face
+/// * `right` - The material index for the right face
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct WedgeMaterialIndices {
+    pub slope: usize,
+    pub back: usize,
+    pub bottom: usize,
+    pub left: usize,
+    pub right: usize,
+}
+
+/// A wedge (ramp), a cuboid cut diagonally so it rises from the front (+Z) to the back (-Z)
+///
+/// # Fields
+/// * `origin` - The center of the wedge's bounding box
+/// * `width` - The width of the wedge (x-axis)
+/// * `height` - The height of the back face (y-axis)
+/// * `depth` - The length of the ramp (z-axis)
+/// * `material_indices` - The material indices for each face of the wedge
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Wedge {
+    pub origin: DVec3,
+    pub width: f64,
+    pub height: f64,
+    pub depth: f64,
+    pub material_indices: WedgeMaterialIndices,
+}
+
+/// How a sphere is broken up into flat faces
+///
+/// # Variants
+/// * `Uv` - Quads between lines of latitude and longitude, with triangles at the poles
+/// * `Geodesic` - An icosahedron whose triangles are split into four `subdivisions` times
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum SphereTessellation {
+    Uv { segments: usize, rings: usize },
+    Geodesic { subdivisions: usize },
+}
+
+/// A sphere, approximated by flat faces whose corners lie on the sphere
+///
+/// # Fields
+/// * `origin` - The center of the sphere
+/// * `radius` - The radius of the sphere
+/// * `tessellation` - How the sphere is broken up into faces
+/// * `material_idx` - The material index for every face
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Sphere {
+    pub origin: DVec3,
+    pub radius: f64,
+    pub tessellation: SphereTessellation,
+    pub material_idx: usize,
+}
+
+/// A capsule, a cylinder with hemispherical caps, approximated by flat faces
+///
+/// # Fields
+/// * `origin` - The center of the capsule
+/// * `radius` - The radius of the cylinder and caps
+/// * `height` - The total length of the capsule along its axis, including the caps
+/// * `segments` - The number of faces around the axis, at least 3
+/// * `rings` - The number of bands of faces in each cap, at least 1
+/// * `axis` - The direction of the capsule's length
+/// * `material_idx` - The material index for every face
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Capsule {
+    pub origin: DVec3,
+    pub radius: f64,
+    pub height: f64,
+    pub segments: usize,
+    pub rings: usize,
+    pub axis: DVec3,
+    pub material_idx: usize,
+}
+
+/// A stair material indices
+///
+/// # Fields
+/// * `tread` - The material index for the top of each step and the landing
+/// * `riser` - The material index for the front of each step
+/// * `stringer` - The material index for the sides, back and bottom of the stairs
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct StairMaterialIndices {
+    pub tread: usize,
+    pub riser: usize,
+    pub stringer: usize,
+}
+
+/// The layout of a staircase
+///
+/// # Variants
+/// * `Straight` - The stairs climb in a straight line towards -Z
+/// * `Spiral` - The stairs climb counterclockwise around the Y axis when seen from above,
+///   starting towards +X. `inner_radius` is the radius of the open well or central column
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum StairShape {
+    Straight,
+    Spiral { inner_radius: f64 },
+}
+
+/// A staircase, built from one solid block per step
+///
+/// # Fields
+/// * `origin` - The floor at the foot of the stairs, the center of the first riser for
+///   straight stairs or the center of the spiral
+/// * `width` - The width of each step
+/// * `rise` - The total height climbed
+/// * `run` - The total length of the steps, measured along the middle of the stairs
+/// * `steps` - The number of steps, at least 1
+/// * `landing` - The length of a flat landing after the top step, measured like `run`
+/// * `shape` - Whether the stairs are straight or spiral
+/// * `material_indices` - The material indices for treads, risers and stringers
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Stairs {
+    pub origin: DVec3,
+    pub width: f64,
+    pub rise: f64,
+    pub run: f64,
+    pub steps: usize,
+    pub landing: Option<f64>,
+    pub shape: StairShape,
+    pub material_indices: StairMaterialIndices,
+}
+
+/// The curve of an arch
+///
+/// # Variants
+/// * `Semicircular` - A half circle, rising half the span
+/// * `Pointed` - Two circular arcs with a radius of the span, meeting at a point
+/// * `Elliptical` - A half ellipse rising `rise` above the legs
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ArchShape {
+    Semicircular,
+    Pointed,
+    Elliptical { rise: f64 },
+}
+
+/// An arch material indices
+///
+/// # Fields
+/// * `faces` - The material index for the front and back faces
+/// * `inner` - The material index for the faces around the opening
+/// * `outer` - The material index for the remaining faces
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ArchMaterialIndices {
+    pub faces: usize,
+    pub inner: usize,
+    pub outer: usize,
+}
+
+/// An arch in the XY plane, standing on the floor and passing through a wall along Z
+///
+/// # Fields
+/// * `origin` - The center of the bottom of the opening
+/// * `span` - The width of the opening (x-axis)
+/// * `leg_height` - The height of the straight sides below the curve (y-axis)
+/// * `depth` - The depth of the arch (z-axis)
+/// * `thickness` - The width of the arch ring and legs around the opening. `None`
+///   fills the opening instead, giving a single convex solid to subtract doorways with
+/// * `segments` - The number of straight pieces along the curve, at least 1
+/// * `shape` - The curve of the arch
+/// * `material_indices` - The material indices for the faces of the arch
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Arch {
+    pub origin: DVec3,
+    pub span: f64,
+    pub leg_height: f64,
+    pub depth: f64,
+    pub thickness: Option<f64>,
+    pub segments: usize,
+    pub shape: ArchShape,
+    pub material_indices: ArchMaterialIndices,
+}
+
+/// A pipe material indices
+///
+/// # Fields
+/// * `outer` - The material index for the outside of the pipe
+/// * `inner` - The material index for the inside of a hollow pipe
+/// * `ends` - The material index for the ends of the pipe
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PipeMaterialIndices {
+    pub outer: usize,
+    pub inner: usize,
+    pub ends: usize,
+}
+
+/// A bent pipe, a segment of a torus around the Y axis
+///
+/// The pipe starts towards +X from the origin and bends counterclockwise when seen from above.
+///
+/// # Fields
+/// * `origin` - The center of the bend
+/// * `bend_radius` - The distance from the origin to the middle of the pipe
+/// * `radius` - The radius of the pipe
+/// * `wall_thickness` - The thickness of the wall of a hollow pipe, `None` for a solid pipe
+/// * `sweep` - The angle of the bend in radians, up to a full turn
+/// * `segments` - The number of straight pieces along the bend, at least 1
+/// * `sides` - The number of faces around the pipe, at least 3
+/// * `material_indices` - The material indices for the faces of the pipe
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Pipe {
+    pub origin: DVec3,
+    pub bend_radius: f64,
+    pub radius: f64,
+    pub wall_thickness: Option<f64>,
+    pub sweep: f64,
+    pub segments: usize,
+    pub sides: usize,
+    pub material_indices: PipeMaterialIndices,
+}
+
+/// An extrusion material indices
+///
+/// # Fields
+/// * `sides` - The material index for the faces swept by the outline
+/// * `start` - The material index for the face at the outline
+/// * `end` - The material index for the face at the far end of the extrusion
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ExtrusionMaterialIndices {
+    pub sides: usize,
+    pub start: usize,
+    pub end: usize,
+}
+
+/// A closed 2D outline on a plane, swept along a vector
+///
+/// A point `(x, y)` of the outline lies at `origin + u_axis * x + v_axis * y`.
+///
+/// # Fields
+/// * `outline` - The points of the outline, which may be concave and wind either way
+/// * `origin` - The position of the outline's origin
+/// * `u_axis` - The direction of the outline's x-axis
+/// * `v_axis` - The direction of the outline's y-axis
+/// * `extrusion` - The vector the outline is swept along, e.g. the plane normal times a depth
+/// * `material_indices` - The material indices for the faces of the extrusion
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Extrusion {
+    pub outline: Vec<DVec2>,
+    pub origin: DVec3,
+    pub u_axis: DVec3,
+    pub v_axis: DVec3,
+    pub extrusion: DVec3,
+    pub material_indices: ExtrusionMaterialIndices,
+}
+
+impl Extrusion {
+    /// An outline on the floor (XZ plane), with its x-axis along X and its
+    /// y-axis along -Z, raised by `height`.
+    pub fn from_floor_plan(
+        outline: Vec<DVec2>,
+        height: f64,
+        material_indices: ExtrusionMaterialIndices,
+    ) -> Self {
+        Self {
+            outline,
+            origin: DVec3::ZERO,
+            u_axis: DVec3::X,
+            v_axis: -DVec3::Z,
+            extrusion: DVec3::Y * height,
+            material_indices,
+        }
+    }
+
+    pub(crate) fn to_world(&self, point: DVec2) -> DVec3 {
+        self.origin + self.u_axis * point.x + self.v_axis * point.y
+    }
+}
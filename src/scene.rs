@@ -3,17 +3,27 @@ use crate::{
     brush::{Brush, BrushSelection},
 };
 
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Layer {
     pub name: String,
     pub brushes: Vec<Brush>,
     pub hidden: bool,
 }
 
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[cfg_attr(feature = "bevy", derive(bevy::prelude::Component))]
 pub struct BrusherScene {
     pub layers: Vec<Layer>,
 }
 
+impl Default for BrusherScene {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl BrusherScene {
     pub fn new() -> Self {
         Self { layers: Vec::new() }
@@ -25,25 +35,31 @@ impl BrusherScene {
         Some(brush)
     }
 
-    pub fn try_select_brush<'a>(&'a mut self, raycast: &Raycast) -> Option<BrushSelection> {
-        for (layer_idx, layer) in self.layers.iter_mut().enumerate() {
+    /// Selects the closest brush hit by the raycast in a visible layer.
+    pub fn try_select_brush(&mut self, raycast: &Raycast) -> Option<BrushSelection> {
+        let mut closest: Option<BrushSelection> = None;
+        for (layer_idx, layer) in self.layers.iter().enumerate() {
             if layer.hidden {
                 continue;
             }
-            for (idx, brush) in layer.brushes.iter_mut().enumerate() {
+            for (idx, brush) in layer.brushes.iter().enumerate() {
                 if let Some(result) = brush.try_select(raycast) {
-                    return Some(BrushSelection {
-                        idx,
-                        layer_idx,
-                        raycast_result: result,
-                    });
+                    if closest.as_ref().map_or(true, |closest| {
+                        result.distance < closest.raycast_result.distance
+                    }) {
+                        closest = Some(BrushSelection {
+                            idx,
+                            layer_idx,
+                            raycast_result: result,
+                        });
+                    }
                 }
             }
         }
-        None
+        closest
     }
 
-    pub fn get_brush_mut<'a>(&'a mut self, layer_idx: usize, idx: usize) -> Option<&'a mut Brush> {
+    pub fn get_brush_mut(&mut self, layer_idx: usize, idx: usize) -> Option<&mut Brush> {
         let layer = self.layers.get_mut(layer_idx)?;
         let brush = layer.brushes.get_mut(idx)?;
         Some(brush)
@@ -54,14 +70,14 @@ impl BrusherScene {
 mod tests {
 
     #[cfg(feature = "bevy")]
-    use bevy::math::DVec3;
+    use bevy::math::{DAffine3, DVec3};
 
     #[cfg(not(feature = "bevy"))]
-    use glam::DVec3;
+    use glam::{DAffine3, DVec3};
 
     use crate::{
         broadphase::Raycast,
-        brush::BooleanOp,
+        brush::{brushlet::tests::test_cuboid, BooleanOp},
         prelude::{Brushlet, BrushletSettings, Cuboid, CuboidMaterialIndices},
     };
 
@@ -96,9 +112,11 @@ mod tests {
         layer.brushes.push(brush0);
         scene.layers.push(layer);
 
-        let raycast = Raycast::new(DVec3::new(0.0, 0.0, -2.0), DVec3::new(0.0, 0.0, 1.0));
+        // Brush 0 is the second brush of the layer
+        let raycast = Raycast::new(DVec3::new(0.0, 0.0, -10.0), DVec3::new(0.0, 0.0, 1.0));
         let selection = scene.try_select_brush(&raycast).unwrap();
-        assert_eq!(selection.idx, 0);
+        assert_eq!(selection.idx, 1);
+        assert_eq!(selection.raycast_result.distance, 6.0);
     }
 
     #[test]
@@ -134,4 +152,91 @@ mod tests {
         let selection = scene.try_select_brush(&raycast);
         assert!(selection.is_none());
     }
+
+    #[test]
+    fn test_try_select_coplanar_faces() {
+        // Two brushlets whose front faces share the z = -0.5 plane
+        let mut brush = Brush::new("Brush 0");
+        for x in [0.0, 3.0] {
+            brush
+                .brushlets
+                .push(test_cuboid(DVec3::new(x, 0.0, 0.0), 1.0));
+        }
+
+        let raycast = Raycast::new(DVec3::new(3.0, 0.0, -2.0), DVec3::Z);
+        assert_eq!(brush.try_select_brushlet(&raycast), Some(1));
+        let raycast = Raycast::new(DVec3::new(1.5, 0.0, -2.0), DVec3::Z);
+        assert_eq!(brush.try_select_brushlet(&raycast), None);
+
+        // The closest brush wins, whatever its layer
+        let mut scene = BrusherScene::new();
+        let mut far = brush.clone();
+        far.transform(DAffine3::from_translation(DVec3::Z * 5.0));
+        for brush in [far, brush] {
+            scene.layers.push(Layer {
+                name: "Test".to_string(),
+                brushes: vec![brush],
+                hidden: false,
+            });
+        }
+        assert!(scene.try_select_brush(&raycast).is_none());
+        let raycast = Raycast::new(DVec3::new(0.0, 0.0, -2.0), DVec3::Z);
+        let selection = scene.try_select_brush(&raycast).unwrap();
+        assert_eq!(selection.layer_idx, 1);
+        assert_eq!(selection.raycast_result.distance, 1.5);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde_round_trip() {
+        use crate::prelude::Knife;
+
+        let mut scene = BrusherScene::new();
+        let mut brush = Brush::new("Brush 0");
+        brush.brushlets.push(Brushlet::from_cuboid(
+            Cuboid {
+                origin: DVec3::new(0.0, 0.0, 0.0),
+                width: 8.0,
+                height: 4.0,
+                depth: 8.0,
+                material_indices: CuboidMaterialIndices {
+                    front: 1,
+                    back: 2,
+                    left: 3,
+                    right: 4,
+                    top: 5,
+                    bottom: 6,
+                },
+            },
+            BrushletSettings {
+                name: "Room 1".to_string(),
+                operation: BooleanOp::Subtract,
+                inverted: true,
+                knives: vec![Knife::new(DVec3::new(1.0, 1.0, 0.0).normalize(), 0.3, 7)],
+            },
+        ));
+        scene.layers.push(Layer {
+            name: "Test".to_string(),
+            brushes: vec![brush],
+            hidden: true,
+        });
+
+        let json = serde_json::to_string(&scene).unwrap();
+        let loaded: BrusherScene = serde_json::from_str(&json).unwrap();
+        assert_eq!(serde_json::to_string(&loaded).unwrap(), json);
+
+        let brushlet = &loaded.layers[0].brushes[0].brushlets[0];
+        assert!(loaded.layers[0].hidden);
+        assert!(brushlet.settings.inverted);
+        assert!(matches!(brushlet.settings.operation, BooleanOp::Subtract));
+        assert_eq!(brushlet.settings.knives[0].material_index, 7);
+
+        let mut materials: Vec<usize> = brushlet
+            .polygons
+            .iter()
+            .map(|polygon| polygon.surface.material_idx)
+            .collect();
+        materials.sort();
+        assert_eq!(materials, vec![1, 2, 3, 4, 5, 6]);
+    }
 }
@@ -3,7 +3,7 @@ use std::{
     ops::BitOr,
 };
 
-use super::polygon::Polygon;
+use super::{brush::BrushError, polygon::Polygon, tolerance::Tolerance};
 
 #[cfg(feature = "bevy")]
 use bevy::math::{DAffine3, DVec2, DVec3};
@@ -42,6 +42,56 @@ impl BitOr for PolygonType {
     }
 }
 
+/// An explicit texture projection for a surface, in the style of Valve 220 map files.
+///
+/// UVs are computed as `point.dot(axis) / scale + offset` for each axis.
+///
+/// # Fields
+/// * `u_axis` - The world space direction of the U axis
+/// * `v_axis` - The world space direction of the V axis
+/// * `offset` - The offset added to the UVs
+/// * `scale` - The world units per UV unit along each axis
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "bevy", derive(bevy::prelude::Reflect))]
+pub struct TextureMapping {
+    pub u_axis: DVec3,
+    pub v_axis: DVec3,
+    pub offset: DVec2,
+    pub scale: DVec2,
+}
+
+impl TextureMapping {
+    pub fn compute_uv(&self, point: DVec3) -> DVec2 {
+        DVec2::new(
+            point.dot(self.u_axis) / self.scale.x + self.offset.x,
+            point.dot(self.v_axis) / self.scale.y + self.offset.y,
+        )
+    }
+
+    /// Transforms the projection so the texture stays locked to the geometry.
+    pub fn transform(&self, transform: DAffine3) -> Self {
+        let inverse_transpose = transform.matrix3.inverse().transpose();
+        let transform_axis = |axis: DVec3, scale: f64, offset: f64| {
+            let axis = inverse_transpose * axis;
+            let length = axis.length();
+            (
+                axis / length,
+                scale / length,
+                offset - transform.translation.dot(axis) / scale,
+            )
+        };
+        let (u_axis, u_scale, u_offset) = transform_axis(self.u_axis, self.scale.x, self.offset.x);
+        let (v_axis, v_scale, v_offset) = transform_axis(self.v_axis, self.scale.y, self.offset.y);
+        Self {
+            u_axis,
+            v_axis,
+            offset: DVec2::new(u_offset, v_offset),
+            scale: DVec2::new(u_scale, v_scale),
+        }
+    }
+}
+
 /// A surface in 3D space.
 ///
 /// A surface is defined by a normal vector and a distance from the origin.
@@ -49,25 +99,30 @@ impl BitOr for PolygonType {
 /// # Fields
 /// * `normal` - The normal vector of the surface
 /// * `distance_from_origin` - The distance from the origin
+/// * `material_idx` - The material index of the surface
+/// * `texture` - An explicit texture projection, world aligned UVs are used when `None`
 #[derive(Debug, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[cfg_attr(feature = "bevy", derive(bevy::prelude::Reflect))]
 pub struct Surface {
     pub normal: DVec3,
     pub distance_from_origin: f64,
     pub material_idx: usize,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub texture: Option<TextureMapping>,
 }
 
+/// Surfaces are equal when they lie on the same plane at the default
+/// [`Tolerance`], use [`Tolerance::same_plane`] to compare them at another one.
 impl Hash for Surface {
     fn hash<H: Hasher>(&self, state: &mut H) {
-        self.quantized_normal().hash(state);
-        self.quantized_distance().hash(state);
+        Tolerance::default().quantize_plane(self).hash(state);
     }
 }
 
 impl PartialEq for Surface {
     fn eq(&self, other: &Self) -> bool {
-        self.quantized_normal() == other.quantized_normal()
-            && self.quantized_distance() == other.quantized_distance()
+        Tolerance::default().same_plane(self, other)
     }
 }
 
@@ -75,37 +130,44 @@ impl Eq for Surface {}
 
 impl Surface {
     pub const EPSILON: f64 = 1e-5;
-    const QUANTIZATION_FACTOR: f64 = 1_000_000.0;
-
-    fn quantize(value: f64) -> i64 {
-        (value * Self::QUANTIZATION_FACTOR).round() as i64
-    }
-
-    fn quantized_normal(&self) -> (i64, i64, i64) {
-        (
-            Self::quantize(self.normal.x),
-            Self::quantize(self.normal.y),
-            Self::quantize(self.normal.z),
-        )
-    }
-
-    fn quantized_distance(&self) -> i64 {
-        Self::quantize(self.distance_from_origin)
-    }
 
     pub fn new(normal: DVec3, distance_from_origin: f64, material_idx: usize) -> Self {
         Self {
             normal,
             distance_from_origin,
             material_idx,
+            texture: None,
         }
     }
 
+    pub fn with_texture(mut self, texture: TextureMapping) -> Self {
+        self.texture = Some(texture);
+        self
+    }
+
     pub fn from_points(a: DVec3, b: DVec3, c: DVec3, material_index: usize) -> Self {
         let normal = (b - a).cross(c - a).normalize();
         Self::new(normal, normal.dot(a), material_index)
     }
 
+    /// Like [`Surface::from_points`], but fails instead of returning a NaN
+    /// normal when the points are collinear.
+    pub fn try_from_points(
+        a: DVec3,
+        b: DVec3,
+        c: DVec3,
+        material_index: usize,
+    ) -> Result<Self, BrushError> {
+        if !(a.is_finite() && b.is_finite() && c.is_finite()) {
+            return Err(BrushError::NonFiniteInput);
+        }
+        let normal = (b - a)
+            .cross(c - a)
+            .try_normalize()
+            .ok_or(BrushError::DegeneratePolygon)?;
+        Ok(Self::new(normal, normal.dot(a), material_index))
+    }
+
     pub fn flip(&mut self) {
         self.normal = -self.normal;
         self.distance_from_origin = -self.distance_from_origin;
@@ -114,6 +176,16 @@ impl Surface {
     pub fn split_polygon(
         &self,
         polygon: &Polygon,
+    ) -> (Vec<Polygon>, Vec<Polygon>, Vec<Polygon>, Vec<Polygon>) {
+        self.split_polygon_with_tolerance(polygon, &Tolerance::default())
+    }
+
+    /// Splits a polygon by the plane into coplanar front, coplanar back, front
+    /// and back pieces, classifying its vertices with the given tolerance.
+    pub fn split_polygon_with_tolerance(
+        &self,
+        polygon: &Polygon,
+        tolerance: &Tolerance,
     ) -> (Vec<Polygon>, Vec<Polygon>, Vec<Polygon>, Vec<Polygon>) {
         let mut coplanar_front = Vec::new();
         let mut coplanar_back = Vec::new();
@@ -124,10 +196,10 @@ impl Surface {
         let mut types = Vec::with_capacity(polygon.vertices.len());
 
         for vertex in &polygon.vertices {
-            let t = self.normal.dot(vertex.pos) - self.distance_from_origin;
-            let typ = if t < -Self::EPSILON {
+            let t = tolerance.plane_distance(self, vertex.pos);
+            let typ = if t < 0.0 {
                 PolygonType::Back
-            } else if t > Self::EPSILON {
+            } else if t > 0.0 {
                 PolygonType::Front
             } else {
                 PolygonType::Coplanar
@@ -159,11 +231,7 @@ impl Surface {
                         f.push(vi.clone());
                     }
                     if ti != PolygonType::Front {
-                        b.push(if ti != PolygonType::Back {
-                            vi.clone()
-                        } else {
-                            vi.clone()
-                        });
+                        b.push(vi.clone());
                     }
                     if (ti as u8 | tj as u8) == PolygonType::Spanning as u8 {
                         let t = (self.distance_from_origin - self.normal.dot(vi.pos))
@@ -173,11 +241,18 @@ impl Surface {
                         b.push(v);
                     }
                 }
+                // The pieces lie on the same plane, keep its material and texture
                 if f.len() >= 3 {
-                    front.push(Polygon::new(f, polygon.surface.material_idx));
+                    front.push(Polygon {
+                        vertices: f,
+                        surface: polygon.surface,
+                    });
                 }
                 if b.len() >= 3 {
-                    back.push(Polygon::new(b, polygon.surface.material_idx));
+                    back.push(Polygon {
+                        vertices: b,
+                        surface: polygon.surface,
+                    });
                 }
             }
         }
@@ -187,11 +262,28 @@ impl Surface {
 
     /// Computes UV coordinates for a point on the plane.
     pub fn compute_uv(&self, point: DVec3) -> DVec2 {
+        if let Some(texture) = &self.texture {
+            return texture.compute_uv(point);
+        }
         let (u_axis, v_axis) = Self::compute_uv_axes(&self.normal);
         let projected = point - self.normal * self.distance_from_origin;
         DVec2::new(projected.dot(u_axis), projected.dot(v_axis))
     }
 
+    /// The texture projection of the surface, falling back to the world
+    /// aligned projection used by [`Surface::compute_uv`].
+    pub fn texture_mapping(&self) -> TextureMapping {
+        self.texture.unwrap_or_else(|| {
+            let (u_axis, v_axis) = Self::compute_uv_axes(&self.normal);
+            TextureMapping {
+                u_axis,
+                v_axis,
+                offset: DVec2::ZERO,
+                scale: DVec2::ONE,
+            }
+        })
+    }
+
     /// Computes UV axes for the plane.
     fn compute_uv_axes(normal: &DVec3) -> (DVec3, DVec3) {
         let up = if normal.x.abs() < 0.9 {
@@ -204,9 +296,16 @@ impl Surface {
         (u_axis, v_axis)
     }
 
+    /// Transforms the plane. Normals are transformed by the inverse transpose
+    /// so non-uniform scaling keeps them perpendicular to the surface.
     pub fn transform(&self, transform: DAffine3) -> Self {
-        let normal = transform.transform_vector3(self.normal);
-        let distance_from_origin = self.distance_from_origin + normal.dot(transform.translation);
-        Self::new(normal, distance_from_origin, self.material_idx)
+        let point = transform.transform_point3(self.normal * self.distance_from_origin);
+        let normal = (transform.matrix3.inverse().transpose() * self.normal).normalize();
+        Self {
+            normal,
+            distance_from_origin: normal.dot(point),
+            material_idx: self.material_idx,
+            texture: self.texture.map(|texture| texture.transform(transform)),
+        }
     }
 }
@@ -0,0 +1,276 @@
+#[cfg(feature = "bevy")]
+use bevy::math::DVec3;
+
+#[cfg(not(feature = "bevy"))]
+use glam::DVec3;
+
+use crate::{predicates::plane_side, surface::Surface};
+
+/// How points are classified against planes.
+///
+/// # Variants
+/// * `Epsilon` - Points within `epsilon` of a plane are on it
+/// * `Exact` - Adaptive precision predicates decide the side exactly, only points exactly on a plane are on it
+///
+/// `Exact` suits snapped input such as integer map coordinates. Intersections
+/// computed during CSG are rounded, so it can leave more slivers than `Epsilon`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "bevy", derive(bevy::prelude::Reflect))]
+pub enum PlaneTest {
+    #[default]
+    Epsilon,
+    Exact,
+}
+
+/// The precision used by CSG and plane based construction.
+///
+/// The defaults suit geometry measured in meters. Scale them along with the
+/// geometry for kilometre sized worlds or millimetre sized detail, see [`Tolerance::scaled`].
+/// Normals and other directions are unit length whatever the size of the
+/// geometry, so they are compared with the fixed [`Tolerance::NORMAL_EPSILON`]
+/// and [`Tolerance::NORMAL_QUANTIZATION`] instead.
+///
+/// # Fields
+/// * `epsilon` - The distance within which points count as on a plane, or as the same point
+/// * `quantization` - The step plane distances are snapped to when comparing planes
+/// * `plane_test` - How points are classified against planes
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "bevy", derive(bevy::prelude::Reflect))]
+pub struct Tolerance {
+    pub epsilon: f64,
+    pub quantization: f64,
+    pub plane_test: PlaneTest,
+}
+
+impl Default for Tolerance {
+    fn default() -> Self {
+        Self {
+            epsilon: Surface::EPSILON,
+            quantization: 1e-6,
+            plane_test: PlaneTest::Epsilon,
+        }
+    }
+}
+
+impl Tolerance {
+    /// The precision of unit normals and directions, such as the sine of the
+    /// angle below which directions count as parallel.
+    pub const NORMAL_EPSILON: f64 = 1e-5;
+
+    /// The step normals are snapped to when comparing planes.
+    pub const NORMAL_QUANTIZATION: f64 = 1e-6;
+
+    /// The default tolerance with distances multiplied by `scale`.
+    pub fn scaled(scale: f64) -> Self {
+        let default = Self::default();
+        Self {
+            epsilon: default.epsilon * scale,
+            quantization: default.quantization * scale,
+            ..default
+        }
+    }
+
+    /// The signed distance of a point to a plane, zero when the point is on it.
+    pub fn plane_distance(&self, surface: &Surface, point: DVec3) -> f64 {
+        match self.plane_test {
+            PlaneTest::Epsilon => {
+                let distance = surface.normal.dot(point) - surface.distance_from_origin;
+                if distance.abs() <= self.epsilon {
+                    0.0
+                } else {
+                    distance
+                }
+            }
+            PlaneTest::Exact => plane_side(surface.normal, surface.distance_from_origin, point),
+        }
+    }
+
+    /// Whether two points are the same within `epsilon`.
+    pub fn same_point(&self, a: DVec3, b: DVec3) -> bool {
+        a.distance_squared(b) < self.epsilon * self.epsilon
+    }
+
+    /// Snaps the plane of a surface to a grid so nearly identical planes
+    /// compare equal, the normal to [`Tolerance::NORMAL_QUANTIZATION`] and the
+    /// distance to `quantization`.
+    pub fn quantize_plane(&self, surface: &Surface) -> [i64; 4] {
+        let quantize = |value: f64, step: f64| (value / step).round() as i64;
+        [
+            quantize(surface.normal.x, Self::NORMAL_QUANTIZATION),
+            quantize(surface.normal.y, Self::NORMAL_QUANTIZATION),
+            quantize(surface.normal.z, Self::NORMAL_QUANTIZATION),
+            quantize(surface.distance_from_origin, self.quantization),
+        ]
+    }
+
+    /// Whether two surfaces lie on the same plane, see [`Tolerance::quantize_plane`].
+    pub fn same_plane(&self, a: &Surface, b: &Surface) -> bool {
+        self.quantize_plane(a) == self.quantize_plane(b)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(feature = "bevy")]
+    use bevy::math::DAffine3;
+
+    #[cfg(not(feature = "bevy"))]
+    use glam::DAffine3;
+
+    use super::*;
+    use crate::{
+        brush::brushlet::tests::{test_cuboid, test_settings},
+        prelude::*,
+    };
+
+    #[test]
+    fn test_scaled_tolerance() {
+        // Smaller than the default epsilon, every corner collapses into one point
+        let size = 5e-6;
+        let surfaces = test_cuboid(DVec3::ZERO, size).surfaces.unwrap();
+        let settings = test_settings();
+        let collapsed = Brushlet::from_surfaces(surfaces.clone(), settings.clone());
+        assert!(collapsed.polygons.is_empty());
+
+        let tolerance = Tolerance::scaled(1e-3);
+        let tiny = Brushlet::from_surfaces_with_tolerance(surfaces, settings, &tolerance);
+        assert_eq!(tiny.polygons.len(), 6);
+        assert!(tiny.aabb.max.abs_diff_eq(DVec3::splat(size / 2.0), 1e-12));
+
+        let joined = tiny.union_with_tolerance(&test_cuboid(DVec3::X * size, size), &tolerance);
+        assert_eq!(joined.polygons.len(), 6);
+        assert!((joined.aabb.max.x - size * 1.5).abs() < 1e-12);
+
+        // Face edits and transforms keep the tolerance
+        let mut moved = tiny.clone();
+        moved.move_face_with_tolerance(0, size, &tolerance).unwrap();
+        assert_eq!(moved.polygons.len(), 6);
+        let extent = moved.aabb.max - moved.aabb.min;
+        assert!((extent.x + extent.y + extent.z - size * 4.0).abs() < 1e-12);
+        let shifted =
+            tiny.transform_with_tolerance(DAffine3::from_translation(DVec3::Y * size), &tolerance);
+        assert_eq!(shifted.polygons.len(), 6);
+        assert!(shifted
+            .aabb
+            .min
+            .abs_diff_eq(DVec3::new(-size, size, -size) / 2.0, 1e-12));
+    }
+
+    #[test]
+    fn test_scaled_tolerance_outlines() {
+        let size = 5e-6;
+        let tolerance = Tolerance::scaled(1e-3);
+
+        // The faces of a tiny brushlet only triangulate at a matching tolerance
+        let tiny = Brushlet::from_surfaces_with_tolerance(
+            test_cuboid(DVec3::ZERO, size).surfaces.unwrap(),
+            test_settings(),
+            &tolerance,
+        );
+        assert!(tiny.polygons[0].indices().is_empty());
+        assert_eq!(tiny.polygons[0].indices_with_tolerance(&tolerance).len(), 6);
+
+        // An L shaped outline collapses at the default tolerance
+        let outline: Vec<DVec2> = [
+            (0.0, 0.0),
+            (2.0, 0.0),
+            (2.0, 1.0),
+            (1.0, 1.0),
+            (1.0, 2.0),
+            (0.0, 2.0),
+        ]
+        .into_iter()
+        .map(|(x, y)| DVec2::new(x, y) * size)
+        .collect();
+        let extrusion = Extrusion::from_floor_plan(
+            outline,
+            size,
+            ExtrusionMaterialIndices {
+                sides: 0,
+                start: 0,
+                end: 0,
+            },
+        );
+        let mut settings = Brush::new("Tiny").settings;
+        assert_eq!(
+            Brush::from_extrusion(extrusion.clone(), settings.clone()).err(),
+            Some(BrushError::DegeneratePolygon)
+        );
+        settings.tolerance = tolerance;
+        let brush = Brush::from_extrusion(extrusion, settings).unwrap();
+        assert_eq!(brush.brushlets.len(), 2);
+        assert!(brush
+            .brushlets
+            .iter()
+            .all(|brushlet| brushlet.polygons.len() == 6));
+    }
+
+    #[test]
+    fn test_scaled_tolerance_keeps_normal_step() {
+        let tolerance = Tolerance::scaled(1e3);
+        let a = Surface::new(DVec3::Y, 1.0, 0);
+        let b = Surface::new(DVec3::new(1e-3, 1.0, 0.0).normalize(), 1.0, 0);
+        assert!(!tolerance.same_plane(&a, &b));
+        assert_eq!(
+            tolerance.quantize_plane(&b)[..3],
+            Tolerance::default().quantize_plane(&b)[..3]
+        );
+
+        // Distances still scale
+        let c = Surface::new(DVec3::Y, 1.0 + 1e-4, 0);
+        assert!(tolerance.same_plane(&a, &c));
+        assert!(!Tolerance::default().same_plane(&a, &c));
+    }
+
+    #[test]
+    fn test_exact_plane_test() {
+        let tolerance = Tolerance {
+            plane_test: PlaneTest::Exact,
+            ..Default::default()
+        };
+        let surface = Surface::new(DVec3::Y, 1.0, 0);
+        assert_eq!(
+            tolerance.plane_distance(&surface, DVec3::new(3.0, 1.0, 0.0)),
+            0.0
+        );
+        assert!(tolerance.plane_distance(&surface, DVec3::new(0.0, 1.0 + 1e-9, 0.0)) > 0.0);
+        assert_eq!(
+            Tolerance::default().plane_distance(&surface, DVec3::new(0.0, 1.0 + 1e-9, 0.0)),
+            0.0
+        );
+
+        // Snapped input gives the same result as the epsilon test
+        let mut brush = Brush::new("Exact");
+        brush.settings.tolerance = tolerance;
+        brush.brushlets.push(test_cuboid(DVec3::ZERO, 2.0));
+        brush
+            .brushlets
+            .push(test_cuboid(DVec3::new(2.0, 0.0, 0.0), 2.0));
+        let mut notch = test_cuboid(DVec3::new(1.0, 1.0, 0.0), 1.0);
+        notch.settings.operation = BooleanOp::Subtract;
+        brush.brushlets.push(notch);
+
+        let exact = brush.to_mesh_data();
+        brush.settings.tolerance = Tolerance::default();
+        let epsilon = brush.to_mesh_data();
+        let area = |mesh_data: &MeshData| {
+            mesh_data
+                .polygons
+                .iter()
+                .map(|polygon| {
+                    let a = polygon.vertices[0].pos;
+                    polygon
+                        .vertices
+                        .windows(2)
+                        .skip(1)
+                        .map(|pair| (pair[0].pos - a).cross(pair[1].pos - a).length() / 2.0)
+                        .sum::<f64>()
+                })
+                .sum::<f64>()
+        };
+        assert_eq!(exact.polygons.len(), epsilon.polygons.len());
+        assert!((area(&exact) - area(&epsilon)).abs() < 1e-9);
+    }
+}
@@ -1,23 +1,37 @@
 use std::collections::HashMap;
 
 #[cfg(feature = "bevy")]
-use bevy::math::DVec3;
+use bevy::math::{DVec2, DVec3};
 
 #[cfg(not(feature = "bevy"))]
-use glam::DVec3;
+use glam::{DVec2, DVec3};
 
 use super::{
     polygon::{Polygon, Vertex},
     surface::Surface,
+    tolerance::Tolerance,
 };
 
 /// Generates vertices from a list of planes.
-pub(crate) fn generate_polygons_from_surfaces(planes: &[Surface]) -> Vec<Polygon> {
-    let plane_vertex_map = generate_vertices(planes);
-    let mut polygons = Vec::new();
+pub(crate) fn generate_polygons_from_surfaces(
+    planes: &[Surface],
+    tolerance: &Tolerance,
+) -> Vec<Polygon> {
+    generate_faces(planes, tolerance)
+        .into_iter()
+        .flatten()
+        .collect()
+}
+
+/// Generates the face of every plane, `None` for planes that don't touch
+/// the solid in at least 3 points.
+pub(crate) fn generate_faces(planes: &[Surface], tolerance: &Tolerance) -> Vec<Option<Polygon>> {
+    let plane_vertices = generate_vertices(planes, tolerance);
+    let mut polygons = Vec::with_capacity(planes.len());
 
-    for (surface, vertices) in plane_vertex_map {
+    for (surface, vertices) in planes.iter().zip(plane_vertices) {
         if vertices.len() < 3 {
+            polygons.push(None);
             continue;
         }
 
@@ -38,45 +52,50 @@ pub(crate) fn generate_polygons_from_surfaces(planes: &[Surface]) -> Vec<Polygon
                 .reverse()
         });
 
-        if polygon_vertices.len() >= 3 {
-            polygons.push(Polygon {
-                vertices: polygon_vertices,
-                surface,
-            });
-        }
+        polygons.push(Some(Polygon {
+            vertices: polygon_vertices,
+            surface: *surface,
+        }));
     }
 
     polygons
 }
 
-/// Generates vertices from a list of planes, grouped by plane.
-pub(crate) fn generate_vertices(planes: &[Surface]) -> HashMap<Surface, Vec<Vertex>> {
+/// Generates vertices from a list of planes, grouped by plane index.
+///
+/// Planes repeating an earlier plane get no vertices. The intersections are
+/// rounded, so points are always kept when within `epsilon` of the planes,
+/// whatever the plane test of the tolerance.
+pub(crate) fn generate_vertices(planes: &[Surface], tolerance: &Tolerance) -> Vec<Vec<Vertex>> {
     let plane_count = planes.len();
-    let mut plane_vertex_map = HashMap::new();
+    let mut plane_vertices = vec![Vec::new(); plane_count];
+
+    let keys: Vec<[i64; 4]> = planes
+        .iter()
+        .map(|plane| tolerance.quantize_plane(plane))
+        .collect();
+    let unique: Vec<bool> = (0..plane_count)
+        .map(|i| !keys[..i].contains(&keys[i]))
+        .collect();
 
-    for i in 0..plane_count {
-        for j in (i + 1)..plane_count {
-            for k in (j + 1)..plane_count {
+    for i in (0..plane_count).filter(|i| unique[*i]) {
+        for j in ((i + 1)..plane_count).filter(|j| unique[*j]) {
+            for k in ((j + 1)..plane_count).filter(|k| unique[*k]) {
                 if let Some(point) = threeway_intersection(&planes[i], &planes[j], &planes[k]) {
                     // Ensure the point is inside or on all planes
                     if planes
                         .iter()
-                        .all(|p| p.normal.dot(point) <= p.distance_from_origin + Surface::EPSILON)
+                        .all(|p| p.normal.dot(point) <= p.distance_from_origin + tolerance.epsilon)
                     {
                         // Add the point to each of the three intersecting planes
-                        for plane in [&planes[i], &planes[j], &planes[k]] {
-                            let vertices = plane_vertex_map
-                                .entry(plane.clone())
-                                .or_insert_with(Vec::new);
+                        for idx in [i, j, k] {
+                            let vertices: &mut Vec<Vertex> = &mut plane_vertices[idx];
 
                             // Ensure the point is unique for this plane
-                            if !vertices.iter().any(|v: &Vertex| {
-                                (v.pos - point).length_squared()
-                                    < Surface::EPSILON * Surface::EPSILON
-                            }) {
+                            if !vertices.iter().any(|v| tolerance.same_point(v.pos, point)) {
                                 vertices.push(Vertex {
                                     pos: point,
-                                    normal: plane.normal,
+                                    normal: planes[idx].normal,
                                 });
                             }
                         }
@@ -86,10 +105,13 @@ pub(crate) fn generate_vertices(planes: &[Surface]) -> HashMap<Surface, Vec<Vert
         }
     }
 
-    plane_vertex_map
+    plane_vertices
 }
 
 /// Finds the intersection point of three planes.
+///
+/// Planes whose normals are too close to parallel, see [`Tolerance::NORMAL_EPSILON`],
+/// have no intersection.
 pub(crate) fn threeway_intersection(p1: &Surface, p2: &Surface, p3: &Surface) -> Option<DVec3> {
     let n1 = &p1.normal;
     let n2 = &p2.normal;
@@ -97,7 +119,7 @@ pub(crate) fn threeway_intersection(p1: &Surface, p2: &Surface, p3: &Surface) ->
 
     let denom = n1.dot(n2.cross(*n3));
 
-    if denom.abs() < Surface::EPSILON {
+    if denom.abs() < Tolerance::NORMAL_EPSILON {
         return None;
     }
 
@@ -108,3 +130,324 @@ pub(crate) fn threeway_intersection(p1: &Surface, p2: &Surface, p3: &Surface) ->
 
     Some(p)
 }
+
+/// The planes of a prism with a convex, planar base `outline` swept along `extrusion`.
+///
+/// `side_materials` holds the material of the side starting at each outline
+/// point, `cap_materials` those of the base and of the far end. The outline
+/// may wind either way.
+pub(crate) fn prism_surfaces(
+    outline: &[DVec3],
+    extrusion: DVec3,
+    side_materials: &[usize],
+    cap_materials: (usize, usize),
+) -> Vec<Surface> {
+    let count = outline.len();
+    let centroid = outline.iter().sum::<DVec3>() / count as f64 + extrusion * 0.5;
+
+    let mut normal = DVec3::ZERO;
+    for i in 0..count {
+        normal += outline[i].cross(outline[(i + 1) % count]);
+    }
+    let mut normal = normal.normalize();
+    if normal.dot(extrusion) < 0.0 {
+        normal = -normal;
+    }
+
+    let mut surfaces = Vec::with_capacity(count + 2);
+    for i in 0..count {
+        let start = outline[i];
+        let edge = outline[(i + 1) % count] - start;
+        let mut side = edge.cross(extrusion).normalize();
+        if side.dot(centroid - start) > 0.0 {
+            side = -side;
+        }
+        surfaces.push(Surface::new(side, side.dot(start), side_materials[i]));
+    }
+    surfaces.push(Surface::new(
+        -normal,
+        -normal.dot(outline[0]),
+        cap_materials.0,
+    ));
+    surfaces.push(Surface::new(
+        normal,
+        normal.dot(outline[0] + extrusion),
+        cap_materials.1,
+    ));
+
+    surfaces
+}
+
+/// Twice the signed area of a 2D outline, positive when it winds counterclockwise.
+pub(crate) fn signed_area_2d(outline: &[DVec2]) -> f64 {
+    let count = outline.len();
+    (0..count)
+        .map(|i| outline[i].perp_dot(outline[(i + 1) % count]))
+        .sum()
+}
+
+/// Removes repeated and collinear points from a closed 2D outline and makes it
+/// wind counterclockwise. Points within `epsilon` of each other, or of the line
+/// through their neighbours, are removed.
+pub(crate) fn clean_outline_2d(outline: &[DVec2], tolerance: &Tolerance) -> Vec<DVec2> {
+    let mut points: Vec<DVec2> = Vec::with_capacity(outline.len());
+    for point in outline {
+        if points
+            .last()
+            .map_or(true, |last| last.distance(*point) > tolerance.epsilon)
+        {
+            points.push(*point);
+        }
+    }
+    while points.len() > 1 && points[0].distance(points[points.len() - 1]) <= tolerance.epsilon {
+        points.pop();
+    }
+
+    let mut removed = true;
+    while removed && points.len() >= 3 {
+        removed = false;
+        for i in 0..points.len() {
+            let count = points.len();
+            let prev = points[(i + count - 1) % count];
+            let next = points[(i + 1) % count];
+            let point = points[i];
+            if (point - prev).perp_dot(next - point).abs()
+                <= tolerance.epsilon * (next - prev).length()
+            {
+                points.remove(i);
+                removed = true;
+                break;
+            }
+        }
+    }
+
+    if signed_area_2d(&points) < 0.0 {
+        points.reverse();
+    }
+    points
+}
+
+/// Whether every corner of a counterclockwise 2D outline turns left, or turns
+/// right by less than `epsilon`.
+pub(crate) fn is_convex_2d(outline: &[DVec2], tolerance: &Tolerance) -> bool {
+    let count = outline.len();
+    (0..count).all(|i| {
+        let prev = outline[(i + count - 1) % count];
+        let next = outline[(i + 1) % count];
+        (outline[i] - prev).perp_dot(next - outline[i])
+            >= -tolerance.epsilon * (next - prev).length()
+    })
+}
+
+/// Splits a simple counterclockwise 2D outline into triangles of point indices
+/// by ear clipping.
+pub(crate) fn triangulate_2d(outline: &[DVec2], tolerance: &Tolerance) -> Vec<[usize; 3]> {
+    let mut remaining: Vec<usize> = (0..outline.len()).collect();
+    let mut triangles = Vec::with_capacity(outline.len().saturating_sub(2));
+
+    while remaining.len() > 3 {
+        let count = remaining.len();
+        let ear = (0..count).find(|&i| {
+            let [a, b, c] = [
+                remaining[(i + count - 1) % count],
+                remaining[i],
+                remaining[(i + 1) % count],
+            ];
+            let [pa, pb, pc] = [outline[a], outline[b], outline[c]];
+            if !is_proper_triangle_2d(pa, pb, pc, tolerance) {
+                return false;
+            }
+            remaining.iter().all(|&other| {
+                other == a
+                    || other == b
+                    || other == c
+                    || !point_in_triangle_2d(outline[other], pa, pb, pc, tolerance)
+            })
+        });
+
+        // Self intersecting outlines may have no ears left, clip a corner anyway
+        let i = ear.unwrap_or(0);
+        triangles.push([
+            remaining[(i + count - 1) % count],
+            remaining[i],
+            remaining[(i + 1) % count],
+        ]);
+        remaining.remove(i);
+    }
+    if remaining.len() == 3 {
+        triangles.push([remaining[0], remaining[1], remaining[2]]);
+    }
+
+    triangles
+}
+
+/// Whether a counterclockwise 2D triangle has its middle corner further than
+/// `epsilon` from the line through the other two, so it works at any size.
+pub(crate) fn is_proper_triangle_2d(a: DVec2, b: DVec2, c: DVec2, tolerance: &Tolerance) -> bool {
+    (b - a).perp_dot(c - b) > tolerance.epsilon * (c - a).length()
+}
+
+/// Whether the point is inside the triangle or within `epsilon` of its edges.
+fn point_in_triangle_2d(point: DVec2, a: DVec2, b: DVec2, c: DVec2, tolerance: &Tolerance) -> bool {
+    (b - a).perp_dot(point - a) >= -tolerance.epsilon * (b - a).length()
+        && (c - b).perp_dot(point - b) >= -tolerance.epsilon * (c - b).length()
+        && (a - c).perp_dot(point - c) >= -tolerance.epsilon * (a - c).length()
+}
+
+/// Splits a simple counterclockwise 2D outline into convex pieces.
+///
+/// The outline is triangulated, then neighbouring pieces are merged whenever the
+/// result stays convex (Hertel-Mehlhorn), which gives at most four times the
+/// optimal number of pieces.
+pub(crate) fn convex_decomposition_2d(outline: &[DVec2], tolerance: &Tolerance) -> Vec<Vec<DVec2>> {
+    let mut pieces: Vec<Vec<usize>> = triangulate_2d(outline, tolerance)
+        .into_iter()
+        .map(Vec::from)
+        .collect();
+
+    let mut merged = true;
+    while merged {
+        merged = false;
+        'search: for i in 0..pieces.len() {
+            for j in (i + 1)..pieces.len() {
+                if let Some(piece) = merge_pieces(&pieces[i], &pieces[j]) {
+                    let points: Vec<DVec2> = piece.iter().map(|idx| outline[*idx]).collect();
+                    if is_convex_2d(&points, tolerance) {
+                        pieces[i] = piece;
+                        pieces.remove(j);
+                        merged = true;
+                        break 'search;
+                    }
+                }
+            }
+        }
+    }
+
+    pieces
+        .into_iter()
+        .map(|piece| {
+            let points: Vec<DVec2> = piece.iter().map(|idx| outline[*idx]).collect();
+            clean_outline_2d(&points, tolerance)
+        })
+        .collect()
+}
+
+/// Joins two counterclockwise pieces along an edge they share, if any.
+fn merge_pieces(a: &[usize], b: &[usize]) -> Option<Vec<usize>> {
+    for i in 0..a.len() {
+        let (start, end) = (a[i], a[(i + 1) % a.len()]);
+        // The shared edge runs the other way around the neighbouring piece
+        if let Some(j) = (0..b.len()).find(|&j| b[j] == end && b[(j + 1) % b.len()] == start) {
+            // Walk `a` from the end of the edge back around to its start, then
+            // `b` from the start of the edge around to its end, skipping the shared points
+            let mut piece: Vec<usize> = (1..=a.len()).map(|k| a[(i + k) % a.len()]).collect();
+            piece.extend((2..b.len()).map(|k| b[(j + k) % b.len()]));
+            return Some(piece);
+        }
+    }
+    None
+}
+
+/// Merges adjacent coplanar polygons sharing a material and texture into
+/// larger convex polygons.
+///
+/// The BSP splits polygons along every plane it is built from and never joins
+/// them back, this undoes the splits that were not needed.
+pub(crate) fn merge_coplanar_polygons(
+    polygons: Vec<Polygon>,
+    tolerance: &Tolerance,
+) -> Vec<Polygon> {
+    let mut groups: HashMap<([i64; 4], usize), Vec<usize>> = HashMap::new();
+    for (idx, polygon) in polygons.iter().enumerate() {
+        groups
+            .entry((
+                tolerance.quantize_plane(&polygon.surface),
+                polygon.surface.material_idx,
+            ))
+            .or_default()
+            .push(idx);
+    }
+
+    let mut polygons: Vec<Option<Polygon>> = polygons.into_iter().map(Some).collect();
+    for group in groups.values() {
+        // Merging can expose new shared edges, keep going until nothing changes
+        let mut merged_any = true;
+        while merged_any {
+            merged_any = false;
+            for (k, &i) in group.iter().enumerate() {
+                for &j in &group[k + 1..] {
+                    let (Some(a), Some(b)) = (&polygons[i], &polygons[j]) else {
+                        continue;
+                    };
+                    if a.surface.texture != b.surface.texture {
+                        continue;
+                    }
+                    if let Some(merged) = merge_polygons(a, b, tolerance) {
+                        polygons[i] = Some(merged);
+                        polygons[j] = None;
+                        merged_any = true;
+                    }
+                }
+            }
+        }
+    }
+
+    polygons.into_iter().flatten().collect()
+}
+
+/// Joins two coplanar polygons along an edge they share, if the result is convex.
+fn merge_polygons(a: &Polygon, b: &Polygon, tolerance: &Tolerance) -> Option<Polygon> {
+    let same = |p: DVec3, q: DVec3| tolerance.same_point(p, q);
+    let (a_len, b_len) = (a.vertices.len(), b.vertices.len());
+
+    for i in 0..a_len {
+        let (start, end) = (a.vertices[i].pos, a.vertices[(i + 1) % a_len].pos);
+        let Some(j) = (0..b_len).find(|&j| {
+            same(b.vertices[j].pos, end) && same(b.vertices[(j + 1) % b_len].pos, start)
+        }) else {
+            continue;
+        };
+
+        // Same walk as `merge_pieces`, the result starts at the end of the
+        // shared edge and its start is the last vertex taken from `a`
+        let mut vertices: Vec<Vertex> = (1..=a_len)
+            .map(|k| a.vertices[(i + k) % a_len].clone())
+            .collect();
+        vertices.extend((2..b_len).map(|k| b.vertices[(j + k) % b_len].clone()));
+
+        // The ends of the shared edge are often left in the middle of a straight
+        // edge, within `epsilon` of the line through their neighbours
+        for idx in [a_len - 1, 0] {
+            let len = vertices.len();
+            let prev = vertices[(idx + len - 1) % len].pos;
+            let next = vertices[(idx + 1) % len].pos;
+            let pos = vertices[idx].pos;
+            let direction = (next - prev).normalize();
+            let along = (pos - prev).dot(direction);
+            if direction.cross(pos - prev).length() < tolerance.epsilon
+                && along > 0.0
+                && along < (next - prev).length()
+            {
+                vertices.remove(idx);
+            }
+        }
+
+        // Every vertex turns the same way, `next` is not more than `epsilon`
+        // outside the line through `prev` and the vertex
+        let normal = a.surface.normal.normalize();
+        let len = vertices.len();
+        let convex = len >= 3
+            && (0..len).all(|idx| {
+                let prev = vertices[(idx + len - 1) % len].pos;
+                let pos = vertices[idx].pos;
+                let next = vertices[(idx + 1) % len].pos;
+                let turn = (pos - prev).normalize().cross(next - pos);
+                turn.dot(normal) > -tolerance.epsilon
+            });
+        return convex.then_some(Polygon {
+            vertices,
+            surface: a.surface,
+        });
+    }
+    None
+}